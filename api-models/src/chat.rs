@@ -7,22 +7,204 @@ pub struct ClientMessageBody {
     pub receiver_id: String,
 }
 
+/// A typing indicator that was not refreshed or stopped for this long is dropped,
+/// by the server and by clients alike.
+pub const TYPING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TypingState {
+    Started,
+    Stopped,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ClientMessage {
     Regular(ClientMessageBody),
+    /// Sent again while the user keeps typing, the server forwards it at a limited rate.
+    Typing {
+        receiver_id: String,
+        state: TypingState,
+    },
+    /// Marks every message of that conversation up to and including this one as read.
+    MarkRead {
+        up_to_message_id: i32,
+    },
+    /// Sent to every member of the room.
+    Room(crate::room::ClientRoomMessageBody),
+    /// Broadcast to everyone in the public lobby, only users shown there may post.
+    Lobby(ClientLobbyMessageBody),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct ClientLobbyMessageBody {
+    pub message: String,
+    pub created_at: String,
+}
+
+/// Lobby messages are not stored, only who is connected at the time gets them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LobbyMessageBody {
+    pub sender_id: uuid::Uuid,
+    pub username: String,
+    pub message: String,
+    pub created_at: String,
+}
+
+/// A message the server refused to take, matched to the sent one by `created_at`.
+/// `receiver_id` is set for a direct message, `room_id` for a room message,
+/// neither for a lobby message.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RejectedBody {
+    #[serde(default)]
+    pub receiver_id: Option<String>,
+    #[serde(default)]
+    pub room_id: Option<i32>,
+    pub created_at: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ServerMessageBody {
+    pub message_id: i32,
     pub message: String,
     pub created_at: String,
     pub sender_id: String,
 }
 
+/// Whether the receiver had a live connection when the message was routed.
+/// A [`ReceiptStatus::Delivered`] receipt follows once the message reached the receiver.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Taken by a connection of the receiver on the same server instance.
+    Accepted,
+    /// Handed to another server instance the receiver is connected to.
+    Forwarded,
+    Queued,
+}
+
+/// Sent back to the author of a message once the server has stored and routed it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AckBody {
+    pub message_id: i32,
+    pub receiver_id: String,
+    pub created_at: String,
+    pub status: DeliveryStatus,
+}
+
+/// What happened to messages after the [`AckBody`], which means sent.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptStatus {
+    Delivered,
+    Read,
+}
+
+/// Sent to the author: for [`ReceiptStatus::Delivered`] the message `up_to_message_id`
+/// reached `peer_id`, for [`ReceiptStatus::Read`] every message of theirs to `peer_id`
+/// up to that one was read.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReceiptBody {
+    pub peer_id: uuid::Uuid,
+    pub up_to_message_id: i32,
+    pub status: ReceiptStatus,
+}
+
+/// Messages from `peer_id` the requesting user has not read.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnreadCount {
+    pub peer_id: uuid::Uuid,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PresenceStatus {
+    Online,
+    /// Connected, but nothing was sent for a while.
+    Away,
+    Offline,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PresenceBody {
+    pub user_id: uuid::Uuid,
+    pub status: PresenceStatus,
+    pub last_seen: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+pub struct PresenceQuery {
+    pub user_ids: Vec<uuid::Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum ServerMessage {
     Regular(ServerMessageBody),
+    Ack(AckBody),
+    Receipt(ReceiptBody),
+    /// A room message, also what the author gets back once it is stored.
+    Room(crate::room::RoomMessageBody),
+    Lobby(LobbyMessageBody),
+    Rejected(RejectedBody),
     Notify,
+    /// A friend came online, went away or went offline.
+    Presence(PresenceBody),
+    /// Never stored; a started indicator expires after [`TYPING_TIMEOUT`].
+    Typing {
+        sender_id: String,
+        /// Shown to the receiver, the sender id if the server could not look it up.
+        username: String,
+        state: TypingState,
+    },
+}
+
+/// A stored direct message as returned by the history endpoint.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryMessage {
+    pub message_id: i32,
+    pub sender_id: uuid::Uuid,
+    pub receiver_id: uuid::Uuid,
+    pub message: String,
+    pub created_at: String,
+    pub delivered_at: Option<String>,
+    pub read_at: Option<String>,
+}
+
+/// `?before=<message_id>&limit=N`, both optional; without `before` the newest messages come first.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct HistoryQuery {
+    pub before: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+/// Newest first. Pass `next_before` as `before` to get the page after this one,
+/// it is `None` once the beginning of the conversation is reached.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryPage {
+    pub messages: Vec<HistoryMessage>,
+    pub next_before: Option<i32>,
+}
+
+/// A peer the user has exchanged direct messages with, and where that conversation stands.
+/// The peer is a [`PeerUser`](crate::user::PeerUser) rather than an [`ApiUser`](crate::user::ApiUser)
+/// on purpose: anyone who ever messaged the user would otherwise learn their email.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConversationSummary {
+    pub peer: crate::user::PeerUser,
+    pub last_message_id: i32,
+    pub last_sender_id: uuid::Uuid,
+    /// The start of the newest message, cut at [`MESSAGE_PREVIEW_CHARS`] characters.
+    pub last_message_preview: String,
+    pub last_message_at: String,
+    pub unread: i64,
+}
+
+pub const MESSAGE_PREVIEW_CHARS: usize = 100;
+
+/// One live websocket connection of the requesting user, e.g. one terminal.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatSession {
+    pub connection_id: uuid::Uuid,
+    pub connected_at: String,
 }
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// From most to least privileged. Owners and admins manage the members below them.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiRoom {
+    pub room_id: i32,
+    pub name: String,
+    pub owner_id: Uuid,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateRoomBody {
+    pub name: String,
+}
+
+/// Adds `user_id` to the room, as a member unless `role` says otherwise.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InviteBody {
+    pub user_id: Uuid,
+    pub role: Option<RoomRole>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoomMember {
+    pub user_id: Uuid,
+    pub role: RoomRole,
+    pub joined_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientRoomMessageBody {
+    pub room_id: i32,
+    pub message: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoomMessageBody {
+    pub room_id: i32,
+    pub message_id: i32,
+    pub sender_id: Uuid,
+    pub message: String,
+    pub created_at: String,
+}
+
+/// Newest first, paged like direct messages with [`crate::chat::HistoryQuery`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoomHistoryPage {
+    pub messages: Vec<RoomMessageBody>,
+    pub next_before: Option<i32>,
+}
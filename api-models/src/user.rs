@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ApiUser {
     pub user_id: Uuid,
     pub username: String,
@@ -10,6 +10,13 @@ pub struct ApiUser {
     pub updated_at: Option<String>,
 }
 
+/// Another user as shown next to a conversation, without private details such as email.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerUser {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
 #[derive(Deserialize)]
 pub struct LoginBody {
     pub username: String,
@@ -33,3 +40,19 @@ pub struct RegisterBody {
     pub email: String,
     pub password: String,
 }
+
+/// `?page=&limit=` of the public user directory, pages start at 1.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct PublicUsersQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// What the public directory shows of a user who opted into it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PublicUser {
+    pub user_id: Uuid,
+    pub username: String,
+    /// Whether they take direct messages from people who are not their friends.
+    pub accept_public_chat: bool,
+}
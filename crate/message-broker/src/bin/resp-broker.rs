@@ -0,0 +1,19 @@
+//! Stand-alone pub/sub server speaking the Redis protocol, for running several
+//! `ruim-server` instances locally without a real Redis.
+//!
+//! Usage: `resp-broker [ADDR]`, the address defaults to `RESP_BROKER_ADDR` or `127.0.0.1:6379`.
+
+use message_broker::RespServer;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let addr = std::env::args()
+        .nth(1)
+        .or_else(|| std::env::var("RESP_BROKER_ADDR").ok())
+        .unwrap_or_else(|| "127.0.0.1:6379".to_string());
+
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    eprintln!("resp-broker listening on {}", listener.local_addr()?);
+
+    RespServer::new().serve(listener).await
+}
@@ -0,0 +1,256 @@
+use std::collections::VecDeque;
+
+use crate::{Message, Pipeline};
+
+/// Bounds for the recent messages every channel keeps around for replay.
+/// A message is dropped once either limit is exceeded.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    pub max_messages: usize,
+    /// Measured against [`Message::time`].
+    pub max_age: Option<chrono::Duration>,
+}
+
+impl Default for CacheConfig {
+    /// Caching disabled.
+    fn default() -> Self {
+        Self {
+            max_messages: 0,
+            max_age: None,
+        }
+    }
+}
+
+/// Which cached messages a new subscriber wants to receive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Replay {
+    #[default]
+    None,
+    All,
+    /// Everything published after the message with this id.
+    /// If that message is no longer cached, everything that is cached.
+    SinceId(String),
+    /// Everything with a [`Message::time`] strictly after this instant.
+    SinceTime(chrono::DateTime<chrono::Utc>),
+}
+
+pub struct SubscribeOptions<M> {
+    pub replay: Replay,
+    /// Applied to every message before it reaches the subscriber, replayed ones included.
+    pub pipeline: Option<Pipeline<M>>,
+}
+
+impl<M> Default for SubscribeOptions<M> {
+    fn default() -> Self {
+        Self {
+            replay: Replay::default(),
+            pipeline: None,
+        }
+    }
+}
+
+impl<M> Clone for SubscribeOptions<M> {
+    fn clone(&self) -> Self {
+        Self {
+            replay: self.replay.clone(),
+            pipeline: self.pipeline.clone(),
+        }
+    }
+}
+
+impl<M> std::fmt::Debug for SubscribeOptions<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubscribeOptions")
+            .field("replay", &self.replay)
+            .field("pipeline", &self.pipeline)
+            .finish()
+    }
+}
+
+impl<M> SubscribeOptions<M> {
+    pub fn replay(mut self, replay: Replay) -> Self {
+        self.replay = replay;
+        self
+    }
+
+    pub fn pipeline(mut self, pipeline: Pipeline<M>) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+}
+
+/// Ring buffer of the most recent messages of one channel, oldest first.
+#[derive(Debug)]
+pub(crate) struct MessageCache<M> {
+    messages: VecDeque<M>,
+}
+
+impl<M> Default for MessageCache<M> {
+    fn default() -> Self {
+        Self {
+            messages: VecDeque::new(),
+        }
+    }
+}
+
+impl<M> MessageCache<M> {
+    pub(crate) fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub(crate) fn push<T>(
+        &mut self,
+        message: M,
+        config: &CacheConfig,
+        now: chrono::DateTime<chrono::Utc>,
+    ) where
+        T: Send + Sync + Clone,
+        M: Message<T>,
+    {
+        if config.max_messages == 0 {
+            return;
+        }
+
+        while self.messages.len() >= config.max_messages {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+        self.evict_expired(config, now);
+    }
+
+    pub(crate) fn evict_expired<T>(
+        &mut self,
+        config: &CacheConfig,
+        now: chrono::DateTime<chrono::Utc>,
+    ) where
+        T: Send + Sync + Clone,
+        M: Message<T>,
+    {
+        let Some(max_age) = config.max_age else {
+            return;
+        };
+
+        while self
+            .messages
+            .front()
+            .is_some_and(|message| now - message.time() > max_age)
+        {
+            self.messages.pop_front();
+        }
+    }
+
+    pub(crate) fn replay<T>(&self, replay: &Replay) -> Vec<M>
+    where
+        T: Send + Sync + Clone,
+        M: Message<T> + Clone,
+    {
+        match replay {
+            Replay::None => Vec::new(),
+            Replay::All => self.messages.iter().cloned().collect(),
+            Replay::SinceId(id) => {
+                let start = self
+                    .messages
+                    .iter()
+                    .rposition(|message| &message.id() == id)
+                    .map_or(0, |position| position + 1);
+                self.messages.range(start..).cloned().collect()
+            }
+            Replay::SinceTime(time) => self
+                .messages
+                .iter()
+                .filter(|message| message.time() > *time)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockMessage;
+
+    fn message_at(id: u32, time: chrono::DateTime<chrono::Utc>) -> MockMessage {
+        MockMessage { id, time }
+    }
+
+    fn secs(seconds: i64) -> chrono::Duration {
+        chrono::Duration::try_seconds(seconds).unwrap()
+    }
+
+    fn ids(messages: &[MockMessage]) -> Vec<u32> {
+        messages.iter().map(|m| m.id).collect()
+    }
+
+    #[test]
+    fn test_cache_keeps_most_recent_messages() {
+        let now = chrono::Utc::now();
+        let config = CacheConfig {
+            max_messages: 3,
+            max_age: None,
+        };
+        let mut cache = MessageCache::default();
+        for id in 0..5 {
+            cache.push(message_at(id, now), &config, now);
+        }
+
+        assert_eq!(ids(&cache.replay(&Replay::All)), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_cache_evicts_expired_messages() {
+        let now = chrono::Utc::now();
+        let config = CacheConfig {
+            max_messages: 10,
+            max_age: Some(secs(60)),
+        };
+        let mut cache = MessageCache::default();
+        cache.push(message_at(0, now - secs(90)), &config, now);
+        cache.push(message_at(1, now - secs(30)), &config, now);
+        cache.push(message_at(2, now), &config, now);
+
+        assert_eq!(ids(&cache.replay(&Replay::All)), vec![1, 2]);
+
+        cache.evict_expired(&config, now + secs(45));
+        assert_eq!(ids(&cache.replay(&Replay::All)), vec![2]);
+    }
+
+    #[test]
+    fn test_replay_since() {
+        let now = chrono::Utc::now();
+        let config = CacheConfig {
+            max_messages: 10,
+            max_age: None,
+        };
+        let mut cache = MessageCache::default();
+        for id in 0..4 {
+            let time = now + secs(id as i64);
+            cache.push(message_at(id, time), &config, now);
+        }
+
+        assert!(cache.replay(&Replay::None).is_empty());
+        assert_eq!(ids(&cache.replay(&Replay::SinceId("1".into()))), vec![2, 3]);
+        assert_eq!(
+            ids(&cache.replay(&Replay::SinceId("3".into()))),
+            Vec::<u32>::new()
+        );
+        // unknown id: the subscriber missed more than we cached, send everything
+        assert_eq!(
+            ids(&cache.replay(&Replay::SinceId("42".into()))),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            ids(&cache.replay(&Replay::SinceTime(now + secs(1)))),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn test_disabled_cache_keeps_nothing() {
+        let now = chrono::Utc::now();
+        let mut cache = MessageCache::default();
+        cache.push(message_at(0, now), &CacheConfig::default(), now);
+
+        assert!(cache.replay(&Replay::All).is_empty());
+    }
+}
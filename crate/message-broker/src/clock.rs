@@ -0,0 +1,17 @@
+//! Wall clock the brokers read instead of calling [`chrono::Utc::now`] directly,
+//! so cache retention can be driven by a virtual clock in tests.
+
+/// Source of "now" for cache retention. Timeouts and backoff use tokio's clock instead.
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> chrono::DateTime<chrono::Utc>;
+}
+
+/// The real time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        chrono::Utc::now()
+    }
+}
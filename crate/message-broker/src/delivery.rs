@@ -0,0 +1,101 @@
+use std::{borrow::Cow, time::Duration};
+
+use tracing::Instrument;
+
+use crate::{stats::Counters, Error, Message, Subscriber};
+
+#[derive(Debug)]
+pub enum DeliveryOutcome {
+    Delivered,
+    /// The subscriber did not accept the message within the delivery timeout.
+    TimedOut,
+    Failed(Error),
+}
+
+impl DeliveryOutcome {
+    pub fn is_delivered(&self) -> bool {
+        matches!(self, Self::Delivered)
+    }
+}
+
+/// Outcome of one publish, one entry per subscriber that was asked to take the message.
+/// Subscribers whose pipeline filtered the message out are not listed.
+#[derive(Debug)]
+pub struct DeliveryReport<S> {
+    pub outcomes: Vec<(S, DeliveryOutcome)>,
+    /// Broker connections a networked backend handed the message to.
+    /// Their subscribers, local ones included, are not listed in `outcomes`.
+    pub remote_receivers: usize,
+}
+
+impl<S> Default for DeliveryReport<S> {
+    fn default() -> Self {
+        Self {
+            outcomes: Vec::new(),
+            remote_receivers: 0,
+        }
+    }
+}
+
+impl<S> DeliveryReport<S> {
+    /// How many subscribers received the message.
+    pub fn delivered(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|(_, outcome)| outcome.is_delivered())
+            .count()
+    }
+
+    /// Subscribers that failed or timed out.
+    pub fn undelivered(&self) -> impl Iterator<Item = &S> {
+        self.outcomes
+            .iter()
+            .filter(|(_, outcome)| !outcome.is_delivered())
+            .map(|(subscriber, _)| subscriber)
+    }
+}
+
+/// Hands every subscriber its message at once, each bounded by `timeout`.
+/// Every delivery runs in its own `deliver` span and is recorded in `counters` if given.
+pub(crate) async fn deliver<'m, T, S, M>(
+    deliveries: impl IntoIterator<Item = (S, Cow<'m, M>)>,
+    timeout: Duration,
+    counters: Option<&Counters>,
+) -> DeliveryReport<S>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M>,
+    M: Message<T> + Clone + 'm,
+{
+    let deliveries = deliveries.into_iter().map(|(subscriber, message)| {
+        let span = tracing::debug_span!(
+            "deliver",
+            message_id = %message.id(),
+            outcome = tracing::field::Empty,
+        );
+        async move {
+            let started = tokio::time::Instant::now();
+            let outcome = match tokio::time::timeout(timeout, subscriber.on_message(&message)).await
+            {
+                Ok(Ok(())) => DeliveryOutcome::Delivered,
+                Ok(Err(err)) => DeliveryOutcome::Failed(err),
+                Err(_) => DeliveryOutcome::TimedOut,
+            };
+            let elapsed = started.elapsed();
+
+            let span = tracing::Span::current();
+            span.record("outcome", tracing::field::debug(&outcome));
+            tracing::trace!(?elapsed, "delivery finished");
+            if let Some(counters) = counters {
+                counters.record_delivery(&outcome, elapsed);
+            }
+            (subscriber, outcome)
+        }
+        .instrument(span)
+    });
+
+    DeliveryReport {
+        outcomes: futures_util::future::join_all(deliveries).await,
+        remote_receivers: 0,
+    }
+}
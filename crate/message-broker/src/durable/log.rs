@@ -0,0 +1,276 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const SEGMENT_EXTENSION: &str = "log";
+/// `len: u32` followed by `checksum: u32`, both little endian.
+const RECORD_HEADER_LEN: usize = 8;
+
+/// Append-only log of one topic, split into segment files named after their first offset.
+/// Every record is length prefixed and checksummed; a torn write at the end of a segment
+/// is cut off when the log is opened again.
+#[derive(Debug)]
+pub(crate) struct TopicLog {
+    dir: PathBuf,
+    segments: Vec<Segment>,
+    segment_bytes: u64,
+    sync_writes: bool,
+}
+
+#[derive(Debug)]
+struct Segment {
+    base: u64,
+    records: u64,
+    bytes: u64,
+    path: PathBuf,
+}
+
+impl Segment {
+    fn end(&self) -> u64 {
+        self.base + self.records
+    }
+}
+
+impl TopicLog {
+    pub(crate) fn open(dir: &Path, segment_bytes: u64, sync_writes: bool) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let Some(base) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            segments.push(Segment::recover(base, path)?);
+        }
+        segments.sort_by_key(|segment| segment.base);
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            segments,
+            segment_bytes,
+            sync_writes,
+        })
+    }
+
+    /// Offset of the oldest record still on disk.
+    pub(crate) fn start_offset(&self) -> u64 {
+        self.segments.first().map_or(0, |segment| segment.base)
+    }
+
+    /// Offset the next appended record will get.
+    pub(crate) fn next_offset(&self) -> u64 {
+        self.segments.last().map_or(0, Segment::end)
+    }
+
+    pub(crate) fn size_bytes(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.bytes).sum()
+    }
+
+    pub(crate) fn append(&mut self, payload: &[u8]) -> io::Result<u64> {
+        let needs_new_segment = self
+            .segments
+            .last()
+            .is_none_or(|segment| segment.bytes >= self.segment_bytes);
+        if needs_new_segment {
+            let base = self.next_offset();
+            let path = self.dir.join(format!("{base:020}.{SEGMENT_EXTENSION}"));
+            File::create(&path)?;
+            self.segments.push(Segment {
+                base,
+                records: 0,
+                bytes: 0,
+                path,
+            });
+        }
+
+        let segment = self
+            .segments
+            .last_mut()
+            .expect("a segment was just ensured");
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&checksum(payload).to_le_bytes());
+        record.extend_from_slice(payload);
+
+        let mut file = OpenOptions::new().append(true).open(&segment.path)?;
+        file.write_all(&record)?;
+        if self.sync_writes {
+            file.sync_data()?;
+        }
+
+        let offset = segment.end();
+        segment.records += 1;
+        segment.bytes += record.len() as u64;
+        Ok(offset)
+    }
+
+    /// Every record at or after `offset`, oldest first.
+    pub(crate) fn read_from(&self, offset: u64) -> io::Result<Vec<(u64, Vec<u8>)>> {
+        let mut records = Vec::new();
+        for segment in self
+            .segments
+            .iter()
+            .filter(|segment| segment.end() > offset)
+        {
+            let data = fs::read(&segment.path)?;
+            let mut position = 0;
+            let mut current = segment.base;
+            while let Some((payload, next)) = read_record(&data, position) {
+                if current >= offset {
+                    records.push((current, payload.to_vec()));
+                }
+                position = next;
+                current += 1;
+            }
+        }
+        Ok(records)
+    }
+
+    /// Deletes whole segments whose records all lie before `offset`.
+    /// The segment being written to is always kept. Returns how many segments were deleted.
+    pub(crate) fn truncate_before(&mut self, offset: u64) -> io::Result<usize> {
+        let mut removed = 0;
+        while self.segments.len() > 1 && self.segments[0].end() <= offset {
+            let segment = self.segments.remove(0);
+            fs::remove_file(&segment.path)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Deletes the oldest segments until at most `max_bytes` remain, keeping the active one.
+    pub(crate) fn enforce_size(&mut self, max_bytes: u64) -> io::Result<usize> {
+        let mut removed = 0;
+        while self.segments.len() > 1 && self.size_bytes() > max_bytes {
+            let segment = self.segments.remove(0);
+            fs::remove_file(&segment.path)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+impl Segment {
+    /// Scans the segment and cuts off anything after the last intact record.
+    fn recover(base: u64, path: PathBuf) -> io::Result<Self> {
+        let data = fs::read(&path)?;
+        let mut position = 0;
+        let mut records = 0;
+        while let Some((_, next)) = read_record(&data, position) {
+            position = next;
+            records += 1;
+        }
+
+        if position < data.len() {
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(position as u64)?;
+        }
+
+        Ok(Self {
+            base,
+            records,
+            bytes: position as u64,
+            path,
+        })
+    }
+}
+
+/// The payload starting at `position` and the position of the next record,
+/// or `None` if there is no complete, intact record there.
+fn read_record(data: &[u8], position: usize) -> Option<(&[u8], usize)> {
+    let header = data.get(position..position + RECORD_HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[..4].try_into().ok()?) as usize;
+    let expected = u32::from_le_bytes(header[4..].try_into().ok()?);
+
+    let start = position + RECORD_HEADER_LEN;
+    let payload = data.get(start..start + len)?;
+    (checksum(payload) == expected).then_some((payload, start + len))
+}
+
+/// 32 bit FNV-1a, enough to tell a torn write from a complete record.
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payloads(records: Vec<(u64, Vec<u8>)>) -> Vec<(u64, String)> {
+        records
+            .into_iter()
+            .map(|(offset, payload)| (offset, String::from_utf8(payload).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_append_rolls_segments_and_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = TopicLog::open(dir.path(), 16, false).unwrap();
+        for i in 0..5 {
+            assert_eq!(log.append(format!("message-{i}").as_bytes()).unwrap(), i);
+        }
+        // every record is larger than a segment, so each one gets its own file
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 5);
+
+        let log = TopicLog::open(dir.path(), 16, false).unwrap();
+        assert_eq!(log.next_offset(), 5);
+        assert_eq!(
+            payloads(log.read_from(3).unwrap()),
+            vec![(3, "message-3".into()), (4, "message-4".into())]
+        );
+    }
+
+    #[test]
+    fn test_torn_write_is_cut_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = TopicLog::open(dir.path(), 1024, false).unwrap();
+        log.append(b"first").unwrap();
+        log.append(b"second").unwrap();
+
+        let path = log.segments[0].path.clone();
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 2)
+            .unwrap();
+
+        let mut log = TopicLog::open(dir.path(), 1024, false).unwrap();
+        assert_eq!(log.next_offset(), 1);
+        assert_eq!(log.append(b"third").unwrap(), 1);
+        assert_eq!(
+            payloads(log.read_from(0).unwrap()),
+            vec![(0, "first".into()), (1, "third".into())]
+        );
+    }
+
+    #[test]
+    fn test_truncate_keeps_active_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = TopicLog::open(dir.path(), 1, false).unwrap();
+        for _ in 0..3 {
+            log.append(b"x").unwrap();
+        }
+
+        assert_eq!(log.truncate_before(2).unwrap(), 2);
+        assert_eq!(log.start_offset(), 2);
+        assert_eq!(log.truncate_before(10).unwrap(), 0);
+        assert_eq!(log.read_from(0).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,740 @@
+//! A broker that writes every published message to disk before delivering it,
+//! so messages for subscribers that are offline or crash survive a restart.
+//!
+//! Each topic gets a directory holding its segmented log and the offset of the next
+//! message every durable subscriber still has to receive. A subscriber's offset only
+//! moves forward once it accepted a message, so after a restart
+//! [`DurableBroker::catch_up`] hands it everything it has not acknowledged yet.
+//! Offsets are written in the background shortly after they move, so a crash may
+//! hand a subscriber its last few messages a second time, but never skips one.
+
+use std::{collections::HashMap, fs, hash::Hash, path::PathBuf, sync::Arc, time::Duration};
+
+use parking_lot::{Mutex, RwLock};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    delivery, pipeline, topic, BrokerReceive, BrokerSend, DeliveryReport, Error, Message, Pipeline,
+    Replay, SubscribeOptions, Subscriber, Topic,
+};
+
+mod log;
+mod offsets;
+
+use log::TopicLog;
+use offsets::{Offsets, OffsetsWriter};
+
+/// A subscriber whose progress is remembered across restarts.
+pub trait DurableSubscriber {
+    /// Stable name the subscriber's offsets are stored under.
+    fn durable_name(&self) -> String;
+}
+
+#[derive(Debug, Clone)]
+pub struct DurableConfig {
+    /// A new segment file is started once the current one reaches this size.
+    pub segment_bytes: u64,
+    /// Oldest segments are deleted past this size per topic, even if not everyone has read them.
+    pub retention_bytes: Option<u64>,
+    /// `fsync` every append and offsets write.
+    /// Turning it off trades durability on power loss for throughput.
+    pub sync_writes: bool,
+    pub delivery_timeout: Duration,
+}
+
+impl Default for DurableConfig {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 4 * 1024 * 1024,
+            retention_bytes: None,
+            sync_writes: true,
+            delivery_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+type SharedTopic<S, M> = Arc<Mutex<TopicState<S, M>>>;
+
+struct TopicState<S, M> {
+    dir: PathBuf,
+    /// Locked on its own, and only ever after the topic, so disk I/O on the log
+    /// can run on a blocking thread without holding the topic.
+    log: Arc<Mutex<TopicLog>>,
+    /// durable name -> offset of the next message that subscriber has to receive
+    offsets: Offsets,
+    /// Live subscribers with the pipeline they subscribed with.
+    subscribers: HashMap<S, Option<Pipeline<M>>>,
+    /// Held from append to commit, so offsets of one topic are committed in order.
+    sending: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<S, M> TopicState<S, M> {
+    fn open(dir: PathBuf, config: &DurableConfig) -> Result<Self, Error> {
+        let log = TopicLog::open(&dir, config.segment_bytes, config.sync_writes)
+            .map_err(storage_error)?;
+        let offsets = offsets::load(&dir).map_err(storage_error)?;
+
+        Ok(Self {
+            dir,
+            log: Arc::new(Mutex::new(log)),
+            offsets,
+            subscribers: HashMap::new(),
+            sending: Arc::default(),
+        })
+    }
+
+    fn save_offsets(&self, writer: &OffsetsWriter) {
+        writer.save(&self.dir, &self.offsets);
+    }
+
+    /// Marks `offset` as received by `name` if it is the next message that subscriber was
+    /// waiting for. Out of order acknowledgements are ignored and the message is delivered again.
+    fn commit(&mut self, name: &str, offset: u64) -> bool {
+        match self.offsets.get_mut(name) {
+            Some(next) if *next == offset => {
+                *next = offset + 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Offset before which every durable subscriber has received everything.
+    fn acked(&self) -> u64 {
+        self.offsets
+            .values()
+            .copied()
+            .min()
+            .unwrap_or_else(|| self.log.lock().next_offset())
+    }
+
+    /// Moves subscribers whose next message was deleted by the size limit to the log start.
+    fn skip_deleted(&mut self, writer: &OffsetsWriter) {
+        let start = self.log.lock().start_offset();
+        let mut moved = false;
+        for next in self.offsets.values_mut().filter(|next| **next < start) {
+            *next = start;
+            moved = true;
+        }
+        if moved {
+            self.save_offsets(writer);
+        }
+    }
+}
+
+/// Drops segments everyone has received, then applies the size limit.
+fn compact_log(
+    log: &mut TopicLog,
+    acked: u64,
+    retention_bytes: Option<u64>,
+) -> Result<usize, Error> {
+    let mut removed = log.truncate_before(acked).map_err(storage_error)?;
+    if let Some(max_bytes) = retention_bytes {
+        removed += log.enforce_size(max_bytes).map_err(storage_error)?;
+    }
+    Ok(removed)
+}
+
+/// Runs blocking disk I/O off the async runtime.
+async fn blocking<R: Send + 'static>(
+    f: impl FnOnce() -> Result<R, Error> + Send + 'static,
+) -> Result<R, Error> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(storage_error)?
+}
+
+#[derive(Clone)]
+pub struct DurableBroker<MsgInner, Sub, Msg>
+where
+    MsgInner: Send + Sync + Clone,
+    Sub: Subscriber<MsgInner, Msg> + DurableSubscriber + Hash + Eq,
+    Msg: Message<MsgInner> + Serialize + DeserializeOwned,
+{
+    root: Arc<PathBuf>,
+    config: DurableConfig,
+    topics: Arc<RwLock<HashMap<String, SharedTopic<Sub, Msg>>>>,
+    offsets: Arc<OffsetsWriter>,
+    _phantom: std::marker::PhantomData<MsgInner>,
+    _phantom2: std::marker::PhantomData<Msg>,
+}
+
+impl<T, S, M> DurableBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + DurableSubscriber + Send + Sync + Clone + Hash + Eq,
+    M: Message<T> + Serialize + DeserializeOwned + Send + Sync + Clone,
+{
+    /// Opens the broker stored under `root`, recovering every topic found there.
+    pub fn open(root: impl Into<PathBuf>, config: DurableConfig) -> Result<Self, Error> {
+        let root = root.into();
+        fs::create_dir_all(&root).map_err(storage_error)?;
+
+        let mut topics = HashMap::new();
+        for entry in fs::read_dir(&root).map_err(storage_error)? {
+            let path = entry.map_err(storage_error)?.path();
+            let Some(topic) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(decode_topic)
+            else {
+                continue;
+            };
+            if path.is_dir() {
+                let state = TopicState::open(path, &config)?;
+                topics.insert(topic, Arc::new(Mutex::new(state)));
+            }
+        }
+
+        Ok(Self {
+            root: Arc::new(root),
+            offsets: Arc::new(OffsetsWriter::spawn(config.sync_writes)),
+            config,
+            topics: Arc::new(RwLock::new(topics)),
+            _phantom: std::marker::PhantomData,
+            _phantom2: std::marker::PhantomData,
+        })
+    }
+
+    fn topic(&self, topic: &str) -> Result<SharedTopic<S, M>, Error> {
+        self.topics
+            .read()
+            .get(topic)
+            .cloned()
+            .ok_or(Error::ChannelDoesNotExist(topic.to_string()))
+    }
+
+    /// Delivers, in order, every message `subscriber` has not acknowledged yet,
+    /// committing its offset after each one. Stops at the first failed delivery.
+    /// Returns how many messages were delivered.
+    ///
+    /// A subscriber that is also subscribed live gets its pipeline applied,
+    /// messages the pipeline filters out count as received.
+    /// Messages published while catching up may reach the subscriber twice.
+    pub async fn catch_up(&self, topic: &str, subscriber: &S) -> Result<usize, Error> {
+        let state = self.topic(topic)?;
+        let name = subscriber.durable_name();
+        let pipeline = state.lock().subscribers.get(subscriber).cloned().flatten();
+        let mut delivered = 0;
+
+        loop {
+            let (log, next) = {
+                let state = state.lock();
+                let Some(next) = state.offsets.get(&name).copied() else {
+                    return Ok(delivered);
+                };
+                (state.log.clone(), next)
+            };
+            let records =
+                blocking(move || log.lock().read_from(next).map_err(storage_error)).await?;
+            if records.is_empty() {
+                return Ok(delivered);
+            }
+
+            for (offset, payload) in records {
+                let message: M = serde_json::from_slice(&payload).map_err(storage_error)?;
+                if let Some(message) = pipeline::apply(pipeline.as_ref(), &message) {
+                    match tokio::time::timeout(
+                        self.config.delivery_timeout,
+                        subscriber.on_message(&message),
+                    )
+                    .await
+                    {
+                        Ok(Ok(())) => {}
+                        Ok(Err(err)) => return Err(err),
+                        Err(_) => return Err(Error::SubscriberGoneBad(name)),
+                    }
+                    delivered += 1;
+                }
+
+                let mut state = state.lock();
+                if state.commit(&name, offset) {
+                    state.save_offsets(&self.offsets);
+                }
+            }
+        }
+    }
+
+    /// Deletes log segments that every durable subscriber of `topic` has received
+    /// and enforces [`DurableConfig::retention_bytes`]. Returns how many segments were deleted.
+    pub fn compact(&self, topic: &str) -> Result<usize, Error> {
+        let state = self.topic(topic)?;
+        let mut state = state.lock();
+        let acked = state.acked();
+        let removed = compact_log(&mut state.log.lock(), acked, self.config.retention_bytes)?;
+        state.skip_deleted(&self.offsets);
+        Ok(removed)
+    }
+
+    /// Subscribes like [`BrokerReceive::add_subscriber_with`] and returns the replayed
+    /// messages, read from the log itself so anything not yet compacted away can be requested.
+    /// The replay goes through `options.pipeline` like live messages do.
+    pub async fn subscribe_with(
+        &self,
+        topic: &str,
+        subscriber: S,
+        options: SubscribeOptions<M>,
+    ) -> Result<Vec<M>, Error> {
+        let state = self.topic(topic)?;
+        // no message is between append and delivery while registering, so everything
+        // before `end` is replayed and everything after it is delivered live
+        let sending = state.lock().sending.clone();
+        let (log, end) = {
+            let _sending = sending.lock().await;
+            let mut state = state.lock();
+            self.register(&mut state, subscriber, options.pipeline.clone());
+            let end = state.log.lock().next_offset();
+            (state.log.clone(), end)
+        };
+        if options.replay == Replay::None {
+            return Ok(Vec::new());
+        }
+
+        let messages = blocking(move || {
+            let log = log.lock();
+            log.read_from(log.start_offset()).map_err(storage_error)
+        })
+        .await?
+        .into_iter()
+        .take_while(|(offset, _)| *offset < end)
+        .map(|(_, payload)| serde_json::from_slice::<M>(&payload).map_err(storage_error))
+        .collect::<Result<Vec<M>, Error>>()?;
+
+        let start = match &options.replay {
+            Replay::None | Replay::All => 0,
+            Replay::SinceId(id) => messages
+                .iter()
+                .rposition(|message| &message.id() == id)
+                .map_or(0, |position| position + 1),
+            Replay::SinceTime(time) => messages
+                .iter()
+                .position(|message| message.time() > *time)
+                .unwrap_or(messages.len()),
+        };
+        let backlog = messages.into_iter().skip(start).collect();
+        Ok(pipeline::apply_all(options.pipeline.as_ref(), backlog))
+    }
+
+    /// A subscriber seen for the first time starts at the end of the log.
+    fn register(&self, state: &mut TopicState<S, M>, subscriber: S, pipeline: Option<Pipeline<M>>) {
+        let name = subscriber.durable_name();
+        if !state.offsets.contains_key(&name) {
+            let end = state.log.lock().next_offset();
+            state.offsets.insert(name, end);
+            state.save_offsets(&self.offsets);
+        }
+        state.subscribers.insert(subscriber, pipeline);
+    }
+
+    /// Offset of the next message `subscriber` has to receive on `topic`.
+    pub fn offset_of(&self, topic: &str, subscriber: &S) -> Result<Option<u64>, Error> {
+        let offset = self
+            .topic(topic)?
+            .lock()
+            .offsets
+            .get(&subscriber.durable_name())
+            .copied();
+        Ok(offset)
+    }
+}
+
+impl<T, S, M> BrokerReceive<T, S, M> for DurableBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + DurableSubscriber + Send + Sync + Clone + Hash + Eq,
+    M: Message<T> + Serialize + DeserializeOwned + Send + Sync + Clone,
+{
+    /// Durable topics are concrete, wildcards are rejected.
+    fn create_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), Error> {
+        let topic: &str = &topic.topic();
+        topic::validate_topic(topic)?;
+
+        let mut topics = self.topics.write();
+        if topics.contains_key(topic) {
+            return Err(Error::ChannelAlreadyExist(topic.to_string()));
+        }
+        let state = TopicState::open(self.root.join(encode_topic(topic)), &self.config)?;
+        topics.insert(topic.to_string(), Arc::new(Mutex::new(state)));
+
+        Ok(())
+    }
+
+    /// Removes the topic together with its log and offsets.
+    fn delete_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), Error> {
+        let topic: &str = &topic.topic();
+        let state = self
+            .topics
+            .write()
+            .remove(topic)
+            .ok_or(Error::ChannelDoesNotExist(topic.to_string()))?;
+        let dir = state.lock().dir.clone();
+        self.offsets.remove_dir(&dir).map_err(storage_error)
+    }
+
+    /// A subscriber seen for the first time starts at the end of the log.
+    fn add_subscriber<K: Topic + ?Sized>(&self, topic: &K, subscriber: S) -> Result<(), Error> {
+        self.add_subscriber_with(topic, subscriber, SubscribeOptions::default())?;
+
+        Ok(())
+    }
+
+    /// Stops live delivery. The stored offset is kept, so the subscriber can catch up later.
+    fn remove_subscriber<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: &S,
+    ) -> Result<bool, Error> {
+        let topic: &str = &topic.topic();
+        Ok(self
+            .topic(topic)?
+            .lock()
+            .subscribers
+            .remove(subscriber)
+            .is_some())
+    }
+
+    fn list_channels(&self) -> Vec<String> {
+        self.topics.read().keys().cloned().collect()
+    }
+
+    fn subscriber_count<K: Topic + ?Sized>(&self, topic: &K) -> Result<usize, Error> {
+        let topic: &str = &topic.topic();
+        Ok(self.topic(topic)?.lock().subscribers.len())
+    }
+
+    fn channels_for(&self, subscriber: &S) -> Vec<String> {
+        self.topics
+            .read()
+            .iter()
+            .filter(|(_, state)| state.lock().subscribers.contains_key(subscriber))
+            .map(|(topic, _)| topic.clone())
+            .collect()
+    }
+
+    /// Replaying reads the log from disk, ask [`DurableBroker::subscribe_with`] for that.
+    fn add_subscriber_with<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: S,
+        options: SubscribeOptions<M>,
+    ) -> Result<Vec<M>, Error> {
+        if options.replay != Replay::None {
+            return Err(Error::Other(
+                "durable replays read the disk, use DurableBroker::subscribe_with".to_string(),
+            ));
+        }
+        let topic: &str = &topic.topic();
+        let state = self.topic(topic)?;
+        self.register(&mut state.lock(), subscriber, options.pipeline);
+
+        Ok(Vec::new())
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, S, M> BrokerSend<T, S, M> for DurableBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + DurableSubscriber + Send + Sync + Clone + Hash + Eq,
+    M: Message<T> + Serialize + DeserializeOwned + Send + Sync + Clone,
+{
+    /// Appends the message to the topic's log, then delivers it to the live subscribers.
+    /// Messages to one topic are sent one at a time, so offsets are committed in order.
+    async fn send_message<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        message: M,
+    ) -> Result<DeliveryReport<S>, Error> {
+        let topic: &str = &topic.topic();
+        let state = self.topic(topic)?;
+        let payload = serde_json::to_vec(&message).map_err(storage_error)?;
+
+        let (log, sending) = {
+            let state = state.lock();
+            (state.log.clone(), state.sending.clone())
+        };
+        let _sending = sending.lock().await;
+        let retention_bytes = self.config.retention_bytes;
+        let (offset, over_retention) = blocking(move || {
+            let mut log = log.lock();
+            let offset = log.append(&payload).map_err(storage_error)?;
+            let over = retention_bytes.is_some_and(|max_bytes| log.size_bytes() > max_bytes);
+            Ok((offset, over))
+        })
+        .await?;
+
+        if over_retention {
+            let (log, acked) = {
+                let state = state.lock();
+                (state.log.clone(), state.acked())
+            };
+            blocking(move || compact_log(&mut log.lock(), acked, retention_bytes)).await?;
+            state.lock().skip_deleted(&self.offsets);
+        }
+
+        let subscribers = state
+            .lock()
+            .subscribers
+            .iter()
+            .map(|(subscriber, pipeline)| (subscriber.clone(), pipeline.clone()))
+            .collect::<Vec<_>>();
+
+        // a message filtered out by a subscriber's pipeline counts as received by it
+        let mut skipped = Vec::new();
+        let mut deliveries = Vec::new();
+        for (subscriber, pipeline) in subscribers {
+            match pipeline::apply(pipeline.as_ref(), &message) {
+                Some(message) => deliveries.push((subscriber, message)),
+                None => skipped.push(subscriber),
+            }
+        }
+        let report = delivery::deliver(deliveries, self.config.delivery_timeout, None).await;
+
+        let mut state = state.lock();
+        let mut committed = false;
+        for subscriber in &skipped {
+            committed |= state.commit(&subscriber.durable_name(), offset);
+        }
+        for (subscriber, outcome) in &report.outcomes {
+            if outcome.is_delivered() {
+                committed |= state.commit(&subscriber.durable_name(), offset);
+            } else {
+                state.subscribers.remove(subscriber);
+            }
+        }
+        if committed {
+            state.save_offsets(&self.offsets);
+        }
+
+        Ok(report)
+    }
+}
+
+fn storage_error(err: impl std::fmt::Display) -> Error {
+    Error::Storage(err.to_string())
+}
+
+/// Topic names become directory names; anything but `[A-Za-z0-9._-]` is percent encoded.
+fn encode_topic(topic: &str) -> String {
+    topic
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'_' | b'-' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+fn decode_topic(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Behaviour, MockMessage, MockSubscriber};
+
+    type TestBroker = DurableBroker<u32, MockSubscriber, MockMessage>;
+
+    fn config(segment_bytes: u64) -> DurableConfig {
+        DurableConfig {
+            segment_bytes,
+            sync_writes: false,
+            ..DurableConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unacked_messages_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let broker = TestBroker::open(dir.path(), config(1024)).unwrap();
+            broker.create_channel("user.1.inbox").unwrap();
+            broker
+                .add_subscriber("user.1.inbox", MockSubscriber::new("phone", Behaviour::Ok))
+                .unwrap();
+            broker
+                .add_subscriber(
+                    "user.1.inbox",
+                    MockSubscriber::new("laptop", Behaviour::Fail),
+                )
+                .unwrap();
+
+            let report = broker
+                .send_message("user.1.inbox", MockMessage::new(1))
+                .await
+                .unwrap();
+            assert_eq!(report.delivered(), 1);
+
+            // the phone goes offline, the failing laptop was evicted on the first message
+            broker
+                .remove_subscriber("user.1.inbox", &MockSubscriber::new("phone", Behaviour::Ok))
+                .unwrap();
+            assert_eq!(broker.subscriber_count("user.1.inbox").unwrap(), 0);
+            for i in 2..4 {
+                broker
+                    .send_message("user.1.inbox", MockMessage::new(i))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let broker = TestBroker::open(dir.path(), config(1024)).unwrap();
+        assert_eq!(broker.list_channels(), vec!["user.1.inbox".to_string()]);
+
+        let phone = MockSubscriber::new("phone", Behaviour::Ok);
+        assert_eq!(broker.catch_up("user.1.inbox", &phone).await.unwrap(), 2);
+        assert_eq!(phone.received(), vec![2, 3]);
+        assert_eq!(broker.offset_of("user.1.inbox", &phone).unwrap(), Some(3));
+
+        let laptop = MockSubscriber::new("laptop", Behaviour::Ok);
+        assert_eq!(broker.catch_up("user.1.inbox", &laptop).await.unwrap(), 3);
+        assert_eq!(laptop.received(), vec![1, 2, 3]);
+
+        // nothing left to catch up on
+        assert_eq!(broker.catch_up("user.1.inbox", &phone).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_compaction_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        // one record per segment
+        let broker = TestBroker::open(dir.path(), config(1)).unwrap();
+        broker.create_channel("room.1").unwrap();
+        let fast = MockSubscriber::new("fast", Behaviour::Ok);
+        let slow = MockSubscriber::new("slow", Behaviour::Ok);
+        broker.add_subscriber("room.1", fast.clone()).unwrap();
+        broker.add_subscriber("room.1", slow.clone()).unwrap();
+        broker.remove_subscriber("room.1", &slow).unwrap();
+
+        for i in 0..4 {
+            broker
+                .send_message("room.1", MockMessage::new(i))
+                .await
+                .unwrap();
+        }
+
+        // slow has not received anything, so nothing may go
+        assert_eq!(broker.compact("room.1").unwrap(), 0);
+
+        assert_eq!(broker.catch_up("room.1", &slow).await.unwrap(), 4);
+        // the active segment is always kept
+        assert_eq!(broker.compact("room.1").unwrap(), 3);
+
+        let late = MockSubscriber::new("late", Behaviour::Ok);
+        let replayed = broker
+            .subscribe_with(
+                "room.1",
+                late,
+                SubscribeOptions::default().replay(Replay::All),
+            )
+            .await
+            .unwrap();
+        assert_eq!(replayed.iter().map(|m| m.id).collect::<Vec<_>>(), vec![3]);
+
+        assert!(matches!(
+            broker.create_channel("room.*"),
+            Err(Error::InvalidTopic(_))
+        ));
+        broker.delete_channel("room.1").unwrap();
+        assert!(TestBroker::open(dir.path(), config(1))
+            .unwrap()
+            .list_channels()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_filtered_messages_count_as_received() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = TestBroker::open(dir.path(), config(1024)).unwrap();
+        broker.create_channel("room.1").unwrap();
+        let odd = MockSubscriber::new("odd", Behaviour::Ok);
+        broker
+            .add_subscriber_with(
+                "room.1",
+                odd.clone(),
+                SubscribeOptions::default()
+                    .pipeline(Pipeline::new().filter(|m: &MockMessage| m.id % 2 == 1)),
+            )
+            .unwrap();
+
+        for i in 0..4 {
+            broker
+                .send_message("room.1", MockMessage::new(i))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(odd.received(), vec![1, 3]);
+        assert_eq!(broker.offset_of("room.1", &odd).unwrap(), Some(4));
+
+        // the replay is filtered the same way
+        let replayed = broker
+            .subscribe_with(
+                "room.1",
+                MockSubscriber::new("late", Behaviour::Ok),
+                SubscribeOptions::default()
+                    .replay(Replay::All)
+                    .pipeline(Pipeline::new().filter(|m: &MockMessage| m.id % 2 == 1)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            replayed.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_sends_commit_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let broker = TestBroker::open(dir.path(), config(1024)).unwrap();
+        broker.create_channel("room.1").unwrap();
+        let slow = MockSubscriber::new("slow", Behaviour::Slow(Duration::from_millis(1)));
+        broker.add_subscriber("room.1", slow.clone()).unwrap();
+
+        let sends = (0..20).map(|i| {
+            let broker = broker.clone();
+            tokio::spawn(async move { broker.send_message("room.1", MockMessage::new(i)).await })
+        });
+        for send in futures_util::future::join_all(sends).await {
+            assert_eq!(send.unwrap().unwrap().delivered(), 1);
+        }
+
+        assert_eq!(broker.offset_of("room.1", &slow).unwrap(), Some(20));
+        assert_eq!(broker.catch_up("room.1", &slow).await.unwrap(), 0);
+        assert!(matches!(
+            broker.add_subscriber_with(
+                "room.1",
+                slow,
+                SubscribeOptions::default().replay(Replay::All)
+            ),
+            Err(Error::Other(_))
+        ));
+    }
+
+    #[test]
+    fn test_topic_encoding_roundtrip() {
+        for topic in ["user.1.inbox", "room/../x", "übung 1", "%41"] {
+            let encoded = encode_topic(topic);
+            assert!(encoded
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"._-%".contains(&b)));
+            assert_eq!(decode_topic(&encoded).as_deref(), Some(topic));
+        }
+    }
+}
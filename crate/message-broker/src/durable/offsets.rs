@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+pub(crate) const OFFSETS_FILE: &str = "offsets.json";
+
+/// Offsets that change within this window are written to disk once.
+const BATCH_WINDOW: Duration = Duration::from_millis(20);
+
+pub(crate) type Offsets = HashMap<String, u64>;
+
+pub(crate) fn load(dir: &Path) -> io::Result<Offsets> {
+    match fs::read(dir.join(OFFSETS_FILE)) {
+        Ok(data) => serde_json::from_slice(&data).map_err(io::Error::other),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(err) => Err(err),
+    }
+}
+
+enum Command {
+    Save(PathBuf, Offsets),
+    /// Removes a topic directory, after dropping whatever was still to be saved there.
+    Remove(PathBuf, mpsc::Sender<io::Result<()>>),
+}
+
+/// Writes offsets on a thread of its own, so neither the async runtime nor a topic lock
+/// waits for the disk. Saves arriving close together are merged into one write per topic.
+///
+/// Offsets only move forward, so losing the latest saves in a crash means some messages
+/// are delivered again, never that one is skipped.
+pub(crate) struct OffsetsWriter {
+    commands: Option<mpsc::Sender<Command>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl OffsetsWriter {
+    /// Without `sync` the files are written but not `fsync`ed.
+    pub(crate) fn spawn(sync: bool) -> Self {
+        let (commands, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("durable-offsets".into())
+            .spawn(move || run(receiver, sync))
+            .expect("failed to spawn the offsets writer");
+
+        Self {
+            commands: Some(commands),
+            thread: Some(thread),
+        }
+    }
+
+    pub(crate) fn save(&self, dir: &Path, offsets: &Offsets) {
+        self.send(Command::Save(dir.to_path_buf(), offsets.clone()));
+    }
+
+    /// Removes `dir` in order with pending saves, so none of them recreates it.
+    pub(crate) fn remove_dir(&self, dir: &Path) -> io::Result<()> {
+        let (done, result) = mpsc::channel();
+        self.send(Command::Remove(dir.to_path_buf(), done));
+        result
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("offsets writer stopped")))
+    }
+
+    fn send(&self, command: Command) {
+        if let Some(commands) = &self.commands {
+            // the thread only ends once this handle is dropped
+            let _ = commands.send(command);
+        }
+    }
+}
+
+impl Drop for OffsetsWriter {
+    /// Waits until everything saved so far is on disk.
+    fn drop(&mut self) {
+        self.commands.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(receiver: mpsc::Receiver<Command>, sync: bool) {
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + BATCH_WINDOW;
+        let mut pending: HashMap<PathBuf, Offsets> = HashMap::new();
+        let mut next = Some(first);
+        loop {
+            match next.take() {
+                Some(Command::Save(dir, offsets)) => {
+                    pending.insert(dir, offsets);
+                }
+                Some(Command::Remove(dir, done)) => {
+                    pending.remove(&dir);
+                    let _ = done.send(fs::remove_dir_all(&dir));
+                }
+                None => {}
+            }
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(command) => next = Some(command),
+                Err(_) => break,
+            }
+        }
+
+        for (dir, offsets) in pending {
+            if let Err(err) = write(&dir, &offsets, sync) {
+                tracing::warn!(?err, dir = %dir.display(), "failed to save offsets");
+            }
+        }
+    }
+}
+
+/// Writes to a temporary file and renames it over the old one, syncing both the file and
+/// the directory, so after a crash the file holds either the old or the new offsets.
+fn write(dir: &Path, offsets: &Offsets, sync: bool) -> io::Result<()> {
+    let data = serde_json::to_vec(offsets).map_err(io::Error::other)?;
+    let tmp = dir.join(format!("{OFFSETS_FILE}.tmp"));
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(&data)?;
+    if sync {
+        file.sync_all()?;
+    }
+    fs::rename(tmp, dir.join(OFFSETS_FILE))?;
+
+    // the rename itself is only durable once the directory entry is
+    #[cfg(unix)]
+    if sync {
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_saves_are_merged_and_flushed_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let kept = dir.path().join("kept");
+        let removed = dir.path().join("removed");
+        fs::create_dir_all(&kept).unwrap();
+        fs::create_dir_all(&removed).unwrap();
+
+        let writer = OffsetsWriter::spawn(false);
+        for next in 1..=3 {
+            writer.save(&kept, &HashMap::from([("phone".to_string(), next)]));
+        }
+        writer.save(&removed, &HashMap::from([("phone".to_string(), 1)]));
+        // the pending save must not bring the directory back
+        writer.remove_dir(&removed).unwrap();
+        drop(writer);
+
+        assert_eq!(
+            load(&kept).unwrap(),
+            HashMap::from([("phone".to_string(), 3)])
+        );
+        assert!(!removed.exists());
+    }
+}
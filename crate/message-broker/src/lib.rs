@@ -1,12 +1,33 @@
-use std::{
-    collections::{HashSet, VecDeque},
-    hash::Hash,
-    sync::Arc,
-};
-
-use dashmap::DashMap;
 use thiserror::Error;
 
+mod cache;
+mod clock;
+mod delivery;
+mod durable;
+mod mailbox;
+mod memory;
+mod pipeline;
+mod redelivery;
+mod request;
+mod resp;
+mod stats;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+mod topic;
+
+pub use cache::{CacheConfig, Replay, SubscribeOptions};
+pub use clock::{Clock, SystemClock};
+pub use delivery::{DeliveryOutcome, DeliveryReport};
+pub use durable::{DurableBroker, DurableConfig, DurableSubscriber};
+pub use mailbox::{Mailbox, MailboxConfig, MailboxMetrics, OverflowPolicy};
+pub use memory::InMemoryBroker;
+pub use pipeline::Pipeline;
+pub use redelivery::{dead_letter_topic, Deduplicator, RedeliveryPolicy, DEAD_LETTER_PREFIX};
+pub use request::{BrokerRequest, ReplyInbox, RequestMessage, REPLY_INBOX_PREFIX};
+pub use resp::{RespBroker, RespConfig, RespServer};
+pub use stats::BrokerStats;
+pub use topic::Topic;
+
 pub trait Message<T>
 where
     T: Send + Sync + Clone,
@@ -31,18 +52,58 @@ where
     S: Subscriber<T, M>,
     M: Message<T>,
 {
-    fn create_channel(&self, topic: &str) -> Result<(), Error>;
-    fn delete_channel(&self, topic: &str) -> Result<(), Error>;
-    fn add_subscriber(&self, topic: &str, subscriber: S) -> Result<(), Error>;
+    fn create_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), Error>;
+    fn delete_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), Error>;
+    fn add_subscriber<K: Topic + ?Sized>(&self, topic: &K, subscriber: S) -> Result<(), Error>;
+    /// Returns `false` if the subscriber was not subscribed to `topic`.
+    fn remove_subscriber<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: &S,
+    ) -> Result<bool, Error>;
+    fn list_channels(&self) -> Vec<String>;
+    fn subscriber_count<K: Topic + ?Sized>(&self, topic: &K) -> Result<usize, Error>;
+    /// Every topic `subscriber` is currently subscribed to.
+    fn channels_for(&self, subscriber: &S) -> Vec<String>;
+
+    /// Subscribes and returns the cached messages selected by `options.replay`, oldest first.
+    /// Messages published after this call are delivered to the subscriber instead,
+    /// so the backlog and the live stream neither overlap nor leave a gap.
+    /// Both go through `options.pipeline` first; subscribing again replaces it.
+    ///
+    /// Backends without a cache replay nothing, backends without pipeline support
+    /// refuse subscriptions that ask for one.
+    fn add_subscriber_with<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: S,
+        options: SubscribeOptions<M>,
+    ) -> Result<Vec<M>, Error> {
+        if options.pipeline.is_some() {
+            return Err(Error::Other(
+                "subscription pipelines are not supported by this broker".to_string(),
+            ));
+        }
+        self.add_subscriber(topic, subscriber)?;
+        Ok(Vec::new())
+    }
 }
 
 #[async_trait::async_trait]
-pub trait BrokerSend<T, M>: Send + Clone
+pub trait BrokerSend<T, S, M>: Send + Clone
 where
     T: Send + Sync + Clone,
+    S: Subscriber<T, M>,
     M: Message<T>,
 {
-    async fn send_message(&self, topic: &str, message: M) -> Result<(), Error>;
+    /// Delivers `message` to every subscriber of `topic` and reports how each delivery went.
+    /// Subscribers that failed or timed out are removed from the topic,
+    /// unless the backend retries them (see [`RedeliveryPolicy`]).
+    async fn send_message<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        message: M,
+    ) -> Result<DeliveryReport<S>, Error>;
 }
 
 #[derive(Debug, Error)]
@@ -58,107 +119,16 @@ pub enum Error {
 
     #[error("Subscriber is dead: {0}")]
     SubscriberGoneBad(String),
-}
-
-#[derive(Clone)]
-pub struct InMemoryBroker<MsgInner, Sub, Msg>
-where
-    MsgInner: Send + Sync + Clone,
-    Sub: Subscriber<MsgInner, Msg> + Hash + Eq,
-    Msg: Message<MsgInner>,
-{
-    channels: Arc<DashMap<String, HashSet<Sub>>>,
-    _phantom: std::marker::PhantomData<MsgInner>,
-    _phantom2: std::marker::PhantomData<Msg>,
-}
-
-impl<T, S, M> Default for InMemoryBroker<T, S, M>
-where
-    T: Send + Sync + Clone,
-    S: Subscriber<T, M> + Hash + Eq,
-    M: Message<T>,
-{
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl<T, S, M> InMemoryBroker<T, S, M>
-where
-    T: Send + Sync + Clone,
-    S: Subscriber<T, M> + Hash + Eq,
-    M: Message<T>,
-{
-    pub fn new() -> Self {
-        InMemoryBroker {
-            channels: Arc::new(DashMap::new()),
-            _phantom: std::marker::PhantomData,
-            _phantom2: std::marker::PhantomData,
-        }
-    }
-}
-
-impl<T, S, M> BrokerReceive<T, S, M> for InMemoryBroker<T, S, M>
-where
-    T: Send + Sync + Clone,
-    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq,
-    M: Message<T> + Send + Sync + Clone,
-{
-    fn create_channel(&self, topic: &str) -> Result<(), Error> {
-        match self.channels.entry(topic.to_string()) {
-            dashmap::mapref::entry::Entry::Occupied(_) => {
-                return Err(Error::ChannelAlreadyExist(topic.to_string()));
-            }
-            dashmap::mapref::entry::Entry::Vacant(_) => {
-                self.channels.insert(topic.to_string(), HashSet::new());
-            }
-        }
 
-        Ok(())
-    }
+    #[error("Invalid topic: {0}")]
+    InvalidTopic(String),
 
-    fn delete_channel(&self, topic: &str) -> Result<(), Error> {
-        match self.channels.remove(topic) {
-            Some(_) => Ok(()),
-            None => Err(Error::ChannelDoesNotExist(topic.to_string())),
-        }
-    }
+    #[error("Storage error: {0}")]
+    Storage(String),
 
-    fn add_subscriber(&self, topic: &str, subscriber: S) -> Result<(), Error> {
-        self.channels
-            .get_mut(topic)
-            .ok_or(Error::ChannelDoesNotExist(topic.to_string()))?
-            .insert(subscriber);
+    #[error("Timed out waiting for a reply: {0}")]
+    Timeout(String),
 
-        Ok(())
-    }
-}
-
-#[async_trait::async_trait]
-impl<T, S, M> BrokerSend<T, M> for InMemoryBroker<T, S, M>
-where
-    T: Send + Sync + Clone,
-    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq,
-    M: Message<T> + Send + Sync + Clone,
-{
-    async fn send_message(&self, topic: &str, message: M) -> Result<(), Error> {
-        let mut vec = VecDeque::new();
-        let mut channel = self
-            .channels
-            .get_mut(topic)
-            .ok_or(Error::ChannelDoesNotExist(topic.to_string()))?;
-
-        // TODO: send message concurrently
-        for subscriber in channel.iter() {
-            if subscriber.on_message(&message).await.is_err() {
-                vec.push_back(subscriber.clone());
-            }
-        }
-
-        while let Some(subscriber) = vec.pop_front() {
-            channel.remove(&subscriber);
-        }
-
-        Ok(())
-    }
+    #[error("Nobody is listening: {0}")]
+    NoResponders(String),
 }
@@ -0,0 +1,371 @@
+//! Bounded per-subscriber queues, so one slow consumer neither stalls a publish
+//! nor makes the broker buffer without limit.
+
+use std::{
+    collections::VecDeque,
+    hash::Hash,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+use crate::{Error, Message, Subscriber};
+
+/// What a full mailbox does with the next message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for room. The broker's delivery timeout still applies,
+    /// so a mailbox that stays full gets the subscriber evicted.
+    #[default]
+    Block,
+    /// Make room by discarding the oldest queued message.
+    DropOldest,
+    /// Discard the incoming message.
+    DropNewest,
+    /// Close the mailbox; the broker then evicts the subscriber.
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MailboxConfig {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for MailboxConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 64,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+impl MailboxConfig {
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+/// Point in time view of a mailbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxMetrics {
+    /// Messages waiting to be handed to the subscriber.
+    pub depth: usize,
+    pub capacity: usize,
+    /// Messages the subscriber accepted.
+    pub delivered: u64,
+    /// Messages discarded by [`OverflowPolicy::DropOldest`] or [`OverflowPolicy::DropNewest`].
+    pub dropped: u64,
+    pub closed: bool,
+}
+
+struct Shared<M> {
+    queue: Mutex<VecDeque<M>>,
+    config: MailboxConfig,
+    closed: AtomicBool,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    /// Woken on every push, pop and close.
+    changed: Notify,
+}
+
+impl<M> Shared<M> {
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.changed.notify_waiters();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// Closes the mailbox once the last handle to it is dropped, which stops the worker.
+struct CloseOnDrop<M>(Arc<Shared<M>>);
+
+impl<M> Drop for CloseOnDrop<M> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Wraps a subscriber in a bounded queue drained by its own task.
+///
+/// Subscribe the mailbox instead of the subscriber. Its `on_message` only enqueues,
+/// so a publish counts the message as delivered as soon as it is queued.
+/// The mailbox closes when the inner subscriber fails, on [`OverflowPolicy::Disconnect`],
+/// on [`Mailbox::close`] or when every clone is dropped; queued messages are discarded.
+///
+/// Mailboxes compare and hash like the subscriber they wrap.
+pub struct Mailbox<T, S, M> {
+    subscriber: S,
+    shared: Arc<Shared<M>>,
+    _guard: Arc<CloseOnDrop<M>>,
+    _phantom: PhantomData<T>,
+}
+
+impl<T, S, M> Clone for Mailbox<T, S, M>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            subscriber: self.subscriber.clone(),
+            shared: self.shared.clone(),
+            _guard: self._guard.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, S, M> Mailbox<T, S, M>
+where
+    T: Send + Sync + Clone + 'static,
+    S: Subscriber<T, M> + Send + Sync + Clone + 'static,
+    M: Message<T> + Send + Sync + Clone + 'static,
+{
+    /// Spawns the task that feeds `subscriber`, so it has to be called within a tokio runtime.
+    pub fn new(subscriber: S, config: MailboxConfig) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::with_capacity(config.capacity)),
+            config: MailboxConfig {
+                capacity: config.capacity.max(1),
+                ..config
+            },
+            closed: AtomicBool::new(false),
+            delivered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            changed: Notify::new(),
+        });
+        tokio::spawn(Self::run(shared.clone(), subscriber.clone()));
+
+        Self {
+            subscriber,
+            _guard: Arc::new(CloseOnDrop(shared.clone())),
+            shared,
+            _phantom: PhantomData,
+        }
+    }
+
+    async fn run(shared: Arc<Shared<M>>, subscriber: S) {
+        loop {
+            let changed = shared.changed.notified();
+            if shared.is_closed() {
+                return;
+            }
+
+            let next = shared.queue.lock().pop_front();
+            let Some(message) = next else {
+                changed.await;
+                continue;
+            };
+            // a blocked publisher may be waiting for the slot we just freed
+            shared.changed.notify_waiters();
+
+            if subscriber.on_message(&message).await.is_err() {
+                shared.close();
+                return;
+            }
+            shared.delivered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T, S, M> Mailbox<T, S, M> {
+    pub fn subscriber(&self) -> &S {
+        &self.subscriber
+    }
+
+    pub fn metrics(&self) -> MailboxMetrics {
+        MailboxMetrics {
+            depth: self.shared.queue.lock().len(),
+            capacity: self.shared.config.capacity,
+            delivered: self.shared.delivered.load(Ordering::Relaxed),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+            closed: self.shared.is_closed(),
+        }
+    }
+
+    pub fn close(&self) {
+        self.shared.close();
+    }
+
+    /// Resolves once the mailbox is closed, for whatever reason.
+    pub async fn closed(&self) {
+        loop {
+            let changed = self.shared.changed.notified();
+            if self.shared.is_closed() {
+                return;
+            }
+            changed.await;
+        }
+    }
+}
+
+impl<T, S: PartialEq, M> PartialEq for Mailbox<T, S, M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.subscriber == other.subscriber
+    }
+}
+
+impl<T, S: Eq, M> Eq for Mailbox<T, S, M> {}
+
+impl<T, S: Hash, M> Hash for Mailbox<T, S, M> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.subscriber.hash(state);
+    }
+}
+
+impl<T, S: std::fmt::Debug, M> std::fmt::Debug for Mailbox<T, S, M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mailbox")
+            .field("subscriber", &self.subscriber)
+            .field("metrics", &self.metrics())
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, S, M> Subscriber<T, M> for Mailbox<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Send + Sync,
+    M: Message<T> + Send + Sync + Clone,
+{
+    async fn on_message(&self, message: &M) -> Result<(), Error> {
+        let shared = &self.shared;
+        loop {
+            let changed = shared.changed.notified();
+            {
+                let mut queue = shared.queue.lock();
+                if shared.is_closed() {
+                    return Err(Error::SubscriberGoneBad("mailbox closed".to_string()));
+                }
+
+                if queue.len() < shared.config.capacity {
+                    queue.push_back(message.clone());
+                    drop(queue);
+                    shared.changed.notify_waiters();
+                    return Ok(());
+                }
+
+                match shared.config.overflow {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropOldest => {
+                        queue.pop_front();
+                        queue.push_back(message.clone());
+                        shared.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    OverflowPolicy::DropNewest => {
+                        shared.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    OverflowPolicy::Disconnect => {
+                        drop(queue);
+                        shared.close();
+                        return Err(Error::SubscriberGoneBad("mailbox full".to_string()));
+                    }
+                }
+            }
+            changed.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::{Behaviour, MockMessage, MockSubscriber};
+
+    /// Takes a second per message.
+    fn slow() -> MockSubscriber {
+        MockSubscriber::new("slow", Behaviour::Slow(Duration::from_secs(1)))
+    }
+
+    type TestMailbox = Mailbox<u32, MockSubscriber, MockMessage>;
+
+    async fn fill(mailbox: &TestMailbox, count: u32) -> Vec<bool> {
+        let mut accepted = Vec::new();
+        for id in 0..count {
+            accepted.push(mailbox.on_message(&MockMessage::new(id)).await.is_ok());
+        }
+        accepted
+    }
+
+    fn settle() -> tokio::time::Sleep {
+        tokio::time::sleep(Duration::from_secs(60))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drop_policies() {
+        let config = MailboxConfig::default().capacity(2);
+
+        let subscriber = slow();
+        let mailbox = TestMailbox::new(
+            subscriber.clone(),
+            config.overflow(OverflowPolicy::DropOldest),
+        );
+        // let the worker take message 0, then 1 and 2 fill the queue
+        mailbox.on_message(&MockMessage::new(0)).await.unwrap();
+        tokio::task::yield_now().await;
+        fill(&mailbox, 5).await;
+        assert_eq!(mailbox.metrics().depth, 2);
+        assert_eq!(mailbox.metrics().dropped, 3);
+        settle().await;
+        assert_eq!(subscriber.received(), vec![0, 3, 4]);
+
+        let subscriber = slow();
+        let mailbox = TestMailbox::new(
+            subscriber.clone(),
+            config.overflow(OverflowPolicy::DropNewest),
+        );
+        assert_eq!(fill(&mailbox, 4).await, vec![true; 4]);
+        settle().await;
+        assert_eq!(subscriber.received(), vec![0, 1]);
+        assert_eq!(mailbox.metrics().dropped, 2);
+        assert_eq!(mailbox.metrics().delivered, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_disconnect_policy_closes_mailbox() {
+        let config = MailboxConfig::default()
+            .capacity(2)
+            .overflow(OverflowPolicy::Disconnect);
+        let mailbox = TestMailbox::new(slow(), config);
+
+        assert_eq!(fill(&mailbox, 3).await, vec![true, true, false]);
+        assert!(mailbox.metrics().closed);
+        mailbox.closed().await;
+        assert!(mailbox.on_message(&MockMessage::new(9)).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_block_policy_waits_for_room() {
+        let subscriber = slow();
+        let mailbox = TestMailbox::new(subscriber.clone(), MailboxConfig::default().capacity(1));
+
+        let start = tokio::time::Instant::now();
+        fill(&mailbox, 4).await;
+        // the worker holds one message and the queue one more, the rest had to wait
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+
+        settle().await;
+        assert_eq!(subscriber.received(), vec![0, 1, 2, 3]);
+        assert_eq!(mailbox.metrics().dropped, 0);
+    }
+}
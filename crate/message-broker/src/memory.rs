@@ -0,0 +1,921 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    hash::Hash,
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::RwLock;
+use tracing::Instrument;
+
+use crate::{
+    cache::MessageCache,
+    delivery, pipeline,
+    redelivery::{self, RedeliveryPolicy},
+    stats::Counters,
+    topic::{self, TopicTrie},
+    BrokerReceive, BrokerSend, BrokerStats, CacheConfig, Clock, DeliveryReport, Error, Message,
+    Pipeline, SubscribeOptions, Subscriber, SystemClock, Topic,
+};
+
+/// How long a single subscriber may take to accept a message before it is evicted.
+const DEFAULT_DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Channel<S, M> {
+    /// Each subscriber with the pipeline it subscribed with.
+    subscribers: HashMap<S, Option<Pipeline<M>>>,
+    cache: MessageCache<M>,
+}
+
+impl<S, M> Default for Channel<S, M> {
+    fn default() -> Self {
+        Self {
+            subscribers: HashMap::new(),
+            cache: MessageCache::default(),
+        }
+    }
+}
+
+/// Channels are keyed by `.` separated topic patterns where `*` matches one token
+/// and a trailing `>` matches the rest, e.g. `room.*.typing` or `user.>`.
+/// Publishing to a concrete topic reaches the subscribers of every channel whose pattern
+/// matches it, and each of those channels caches the message.
+#[derive(Clone)]
+pub struct InMemoryBroker<MsgInner, Sub, Msg>
+where
+    MsgInner: Send + Sync + Clone,
+    Sub: Subscriber<MsgInner, Msg> + Hash + Eq,
+    Msg: Message<MsgInner>,
+{
+    channels: Arc<RwLock<TopicTrie<Channel<Sub, Msg>>>>,
+    cache_config: CacheConfig,
+    delivery_timeout: Duration,
+    auto_create_channels: bool,
+    redelivery: Option<RedeliveryPolicy>,
+    counters: Arc<Counters>,
+    clock: Arc<dyn Clock>,
+    _phantom: std::marker::PhantomData<MsgInner>,
+    _phantom2: std::marker::PhantomData<Msg>,
+}
+
+impl<T, S, M> Default for InMemoryBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Hash + Eq,
+    M: Message<T>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, S, M> InMemoryBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Hash + Eq,
+    M: Message<T>,
+{
+    pub fn new() -> Self {
+        InMemoryBroker {
+            channels: Arc::new(RwLock::new(TopicTrie::default())),
+            cache_config: CacheConfig::default(),
+            delivery_timeout: DEFAULT_DELIVERY_TIMEOUT,
+            auto_create_channels: false,
+            redelivery: None,
+            counters: Arc::default(),
+            clock: Arc::new(SystemClock),
+            _phantom: std::marker::PhantomData,
+            _phantom2: std::marker::PhantomData,
+        }
+    }
+
+    /// Keeps recent messages of every channel so new subscribers can ask for a replay.
+    pub fn with_cache(mut self, config: CacheConfig) -> Self {
+        self.cache_config = config;
+        self
+    }
+
+    /// Upper bound for a single [`Subscriber::on_message`] call.
+    pub fn with_delivery_timeout(mut self, timeout: Duration) -> Self {
+        self.delivery_timeout = timeout;
+        self
+    }
+
+    /// Lets `add_subscriber` create a missing channel instead of failing.
+    pub fn with_auto_create_channels(mut self, auto_create: bool) -> Self {
+        self.auto_create_channels = auto_create;
+        self
+    }
+
+    /// Source of "now" for cache retention, the system time unless replaced.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Retries failed and timed out deliveries in the background instead of evicting the
+    /// subscriber right away. A subscriber is only evicted once a message used up all
+    /// attempts; that message is then published to [`redelivery::dead_letter_topic`].
+    pub fn with_redelivery(mut self, policy: RedeliveryPolicy) -> Self {
+        self.redelivery = Some(policy);
+        self
+    }
+
+    /// Delivers to every channel matching `topic` and returns the matched patterns
+    /// along with the report, without evicting anyone.
+    async fn publish(
+        &self,
+        topic: &str,
+        message: &M,
+    ) -> Result<(Vec<String>, DeliveryReport<S>), Error>
+    where
+        S: Clone,
+        M: Clone,
+    {
+        topic::validate_topic(topic)?;
+
+        // snapshot the subscribers so no lock is held while they are awaited,
+        // a subscriber matched by several patterns still gets the message once,
+        // through the pipeline of the most specific pattern
+        let mut matched = Vec::new();
+        let mut subscribers = HashMap::new();
+        {
+            let mut channels = self.channels.write();
+            let now = self.clock.now();
+            channels.for_each_match_mut(topic, |pattern, channel| {
+                channel.cache.push(message.clone(), &self.cache_config, now);
+                for (subscriber, pipeline) in &channel.subscribers {
+                    if let Entry::Vacant(entry) = subscribers.entry(subscriber.clone()) {
+                        entry.insert(pipeline.clone());
+                    }
+                }
+                matched.push(pattern.to_string());
+            });
+        }
+        if matched.is_empty() {
+            return Err(Error::ChannelDoesNotExist(topic.to_string()));
+        }
+
+        let deliveries = subscribers
+            .into_iter()
+            .filter_map(|(subscriber, pipeline)| {
+                let message = pipeline::apply(pipeline.as_ref(), message)?;
+                Some((subscriber, message))
+            });
+        let report =
+            delivery::deliver(deliveries, self.delivery_timeout, Some(&self.counters)).await;
+        Ok((matched, report))
+    }
+
+    fn evict<'a>(&self, patterns: &[String], subscribers: impl IntoIterator<Item = &'a S>)
+    where
+        S: 'a,
+    {
+        let subscribers: Vec<&S> = subscribers.into_iter().collect();
+        let mut evicted = 0;
+        let mut channels = self.channels.write();
+        for pattern in patterns {
+            if let Some(channel) = channels.get_mut(pattern) {
+                for subscriber in &subscribers {
+                    if channel.subscribers.remove(*subscriber).is_some() {
+                        evicted += 1;
+                    }
+                }
+            }
+        }
+        if evicted > 0 {
+            tracing::debug!(evicted, "evicted subscribers");
+            self.counters.record_evictions(evicted);
+        }
+    }
+
+    /// Channel and subscriber counts along with the totals since the broker was created.
+    pub fn stats(&self) -> BrokerStats {
+        let mut stats = self.counters.snapshot();
+        for (_, channel) in self.channels.read().iter() {
+            stats.channels += 1;
+            stats.subscriptions += channel.subscribers.len();
+            stats.cached_messages += channel.cache.len();
+        }
+        stats
+    }
+
+    /// The pipeline `subscriber` is subscribed with on the first of `patterns` it is still on,
+    /// `None` if it left all of them.
+    fn subscription(&self, patterns: &[String], subscriber: &S) -> Option<Option<Pipeline<M>>> {
+        let channels = self.channels.read();
+        patterns.iter().find_map(|pattern| {
+            channels
+                .get(pattern)
+                .and_then(|channel| channel.subscribers.get(subscriber))
+                .cloned()
+        })
+    }
+}
+
+impl<T, S, M> InMemoryBroker<T, S, M>
+where
+    T: Send + Sync + Clone + 'static,
+    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq + 'static,
+    M: Message<T> + Send + Sync + Clone + 'static,
+{
+    /// Keeps offering `message` to `subscriber` until it is acknowledged, the subscriber
+    /// leaves every matched channel, its pipeline filters the message out or the attempts run out.
+    async fn redeliver(
+        self,
+        topic: String,
+        patterns: Vec<String>,
+        subscriber: S,
+        message: M,
+        policy: RedeliveryPolicy,
+    ) {
+        for attempt in 1..policy.max_attempts {
+            tokio::time::sleep(policy.backoff(attempt)).await;
+            let Some(pipeline) = self.subscription(&patterns, &subscriber) else {
+                return;
+            };
+            let Some(filtered) = pipeline::apply(pipeline.as_ref(), &message) else {
+                return;
+            };
+
+            let delivery = std::iter::once((subscriber.clone(), filtered));
+            let report =
+                delivery::deliver(delivery, self.delivery_timeout, Some(&self.counters)).await;
+            if report.delivered() > 0 {
+                return;
+            }
+        }
+
+        tracing::debug!(%topic, message_id = %message.id(), "redelivery exhausted, dead-lettering");
+        self.counters.record_dead_letter();
+        self.evict(&patterns, [&subscriber]);
+        // dead letters are delivered once, a failing dead-letter subscriber is just evicted
+        if let Ok((matched, report)) = self
+            .publish(&redelivery::dead_letter_topic(&topic), &message)
+            .await
+        {
+            self.evict(&matched, report.undelivered());
+        }
+    }
+}
+
+impl<T, S, M> BrokerReceive<T, S, M> for InMemoryBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq,
+    M: Message<T> + Send + Sync + Clone,
+{
+    fn create_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), Error> {
+        let topic: &str = &topic.topic();
+        topic::validate_pattern(topic)?;
+
+        let mut channels = self.channels.write();
+        let slot = channels.slot(topic);
+        if slot.is_some() {
+            return Err(Error::ChannelAlreadyExist(topic.to_string()));
+        }
+        *slot = Some(Channel::default());
+
+        Ok(())
+    }
+
+    fn delete_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), Error> {
+        let topic: &str = &topic.topic();
+        match self.channels.write().remove(topic) {
+            Some(_) => Ok(()),
+            None => Err(Error::ChannelDoesNotExist(topic.to_string())),
+        }
+    }
+
+    fn add_subscriber<K: Topic + ?Sized>(&self, topic: &K, subscriber: S) -> Result<(), Error> {
+        self.add_subscriber_with(topic, subscriber, SubscribeOptions::default())?;
+
+        Ok(())
+    }
+
+    fn remove_subscriber<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: &S,
+    ) -> Result<bool, Error> {
+        let topic: &str = &topic.topic();
+        let removed = self
+            .channels
+            .write()
+            .get_mut(topic)
+            .ok_or(Error::ChannelDoesNotExist(topic.to_string()))?
+            .subscribers
+            .remove(subscriber)
+            .is_some();
+
+        Ok(removed)
+    }
+
+    fn list_channels(&self) -> Vec<String> {
+        self.channels
+            .read()
+            .iter()
+            .into_iter()
+            .map(|(topic, _)| topic)
+            .collect()
+    }
+
+    fn subscriber_count<K: Topic + ?Sized>(&self, topic: &K) -> Result<usize, Error> {
+        let topic: &str = &topic.topic();
+        let count = self
+            .channels
+            .read()
+            .get(topic)
+            .ok_or(Error::ChannelDoesNotExist(topic.to_string()))?
+            .subscribers
+            .len();
+
+        Ok(count)
+    }
+
+    fn channels_for(&self, subscriber: &S) -> Vec<String> {
+        self.channels
+            .read()
+            .iter()
+            .into_iter()
+            .filter(|(_, channel)| channel.subscribers.contains_key(subscriber))
+            .map(|(topic, _)| topic)
+            .collect()
+    }
+
+    fn add_subscriber_with<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: S,
+        options: SubscribeOptions<M>,
+    ) -> Result<Vec<M>, Error> {
+        let topic: &str = &topic.topic();
+        topic::validate_pattern(topic)?;
+
+        let mut channels = self.channels.write();
+        let slot = channels.slot(topic);
+        if slot.is_none() && self.auto_create_channels {
+            *slot = Some(Channel::default());
+        }
+        let Some(channel) = slot.as_mut() else {
+            // do not leave the path created by `slot` behind
+            channels.remove(topic);
+            return Err(Error::ChannelDoesNotExist(topic.to_string()));
+        };
+
+        // both happen under the lock, so no message can slip in between
+        channel
+            .cache
+            .evict_expired(&self.cache_config, self.clock.now());
+        let backlog = pipeline::apply_all(
+            options.pipeline.as_ref(),
+            channel.cache.replay(&options.replay),
+        );
+        channel.subscribers.insert(subscriber, options.pipeline);
+
+        Ok(backlog)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, S, M> BrokerSend<T, S, M> for InMemoryBroker<T, S, M>
+where
+    T: Send + Sync + Clone + 'static,
+    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq + 'static,
+    M: Message<T> + Send + Sync + Clone + 'static,
+{
+    async fn send_message<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        message: M,
+    ) -> Result<DeliveryReport<S>, Error> {
+        let topic: &str = &topic.topic();
+        let span = tracing::debug_span!(
+            "send_message",
+            %topic,
+            message_id = %message.id(),
+            delivered = tracing::field::Empty,
+            undelivered = tracing::field::Empty,
+        );
+        let (matched, report) = self
+            .publish(topic, &message)
+            .instrument(span.clone())
+            .await?;
+        self.counters.record_publish();
+        span.record("delivered", report.delivered());
+        span.record("undelivered", report.undelivered().count());
+
+        match self.redelivery {
+            Some(policy) if policy.max_attempts > 1 => {
+                for subscriber in report.undelivered() {
+                    let retry = self.clone().redeliver(
+                        topic.to_string(),
+                        matched.clone(),
+                        subscriber.clone(),
+                        message.clone(),
+                        policy,
+                    );
+                    tokio::spawn(retry);
+                }
+            }
+            _ => self.evict(&matched, report.undelivered()),
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{Behaviour, MockMessage, MockSubscriber, VirtualClock},
+        DeliveryOutcome, Replay,
+    };
+
+    type TestBroker = InMemoryBroker<u32, MockSubscriber, MockMessage>;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_message_evicts_slow_and_failed_subscribers() {
+        let broker = TestBroker::new().with_delivery_timeout(Duration::from_secs(1));
+        broker.create_channel("topic").unwrap();
+
+        let ok = MockSubscriber::new("ok", Behaviour::Ok);
+        let fail = MockSubscriber::new("fail", Behaviour::Fail);
+        let slow = MockSubscriber::new("slow", Behaviour::Slow(Duration::from_secs(10)));
+        for subscriber in [&ok, &fail, &slow] {
+            broker.add_subscriber("topic", subscriber.clone()).unwrap();
+        }
+
+        let report = broker
+            .send_message("topic", MockMessage::new(1))
+            .await
+            .unwrap();
+        assert_eq!(report.outcomes.len(), 3);
+        assert_eq!(report.delivered(), 1);
+        for (subscriber, outcome) in &report.outcomes {
+            match subscriber.name() {
+                "ok" => assert!(outcome.is_delivered()),
+                "fail" => assert!(matches!(outcome, DeliveryOutcome::Failed(_))),
+                "slow" => assert!(matches!(outcome, DeliveryOutcome::TimedOut)),
+                _ => unreachable!(),
+            }
+        }
+
+        let report = broker
+            .send_message("topic", MockMessage::new(2))
+            .await
+            .unwrap();
+        assert_eq!(report.outcomes.len(), 1);
+        assert_eq!(ok.received(), vec![1, 2]);
+        assert!(slow.received().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_subscribers_are_awaited_concurrently() {
+        let broker = TestBroker::new().with_delivery_timeout(Duration::from_secs(5));
+        broker.create_channel("topic").unwrap();
+        for name in ["a", "b", "c"] {
+            let subscriber = MockSubscriber::new(name, Behaviour::Slow(Duration::from_secs(2)));
+            broker.add_subscriber("topic", subscriber).unwrap();
+        }
+
+        let start = tokio::time::Instant::now();
+        let report = broker
+            .send_message("topic", MockMessage::new(1))
+            .await
+            .unwrap();
+
+        assert_eq!(report.delivered(), 3);
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_replay_backlog_on_subscribe() {
+        let broker = TestBroker::new().with_cache(CacheConfig {
+            max_messages: 2,
+            max_age: None,
+        });
+        broker.create_channel("topic").unwrap();
+        for id in 0..3 {
+            broker
+                .send_message("topic", MockMessage::new(id))
+                .await
+                .unwrap();
+        }
+
+        let subscriber = MockSubscriber::new("late", Behaviour::Ok);
+        let backlog = broker
+            .add_subscriber_with(
+                "topic",
+                subscriber.clone(),
+                SubscribeOptions::default().replay(Replay::SinceId("1".into())),
+            )
+            .unwrap();
+        assert_eq!(backlog.iter().map(|m| m.id).collect::<Vec<_>>(), vec![2]);
+
+        broker
+            .send_message("topic", MockMessage::new(3))
+            .await
+            .unwrap();
+        assert_eq!(subscriber.received(), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn test_replay_goes_through_pipeline() {
+        let broker = TestBroker::new().with_cache(CacheConfig {
+            max_messages: 10,
+            max_age: None,
+        });
+        broker.create_channel("topic").unwrap();
+        for id in 0..4 {
+            broker
+                .send_message("topic", MockMessage::new(id))
+                .await
+                .unwrap();
+        }
+
+        let backlog = broker
+            .add_subscriber_with(
+                "topic",
+                MockSubscriber::new("late", Behaviour::Ok),
+                SubscribeOptions::default().replay(Replay::All).pipeline(
+                    Pipeline::new()
+                        .filter(|m: &MockMessage| m.id.is_multiple_of(2))
+                        .map(|m: MockMessage| MockMessage::new(m.id * 10)),
+                ),
+            )
+            .unwrap();
+        assert_eq!(
+            backlog.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![0, 20]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cache_retention_follows_virtual_clock() {
+        let clock = VirtualClock::new();
+        let broker = TestBroker::new()
+            .with_clock(clock.clone())
+            .with_cache(CacheConfig {
+                max_messages: 10,
+                max_age: Some(chrono::Duration::try_seconds(60).unwrap()),
+            });
+        broker.create_channel("topic").unwrap();
+
+        broker
+            .send_message("topic", clock.message(1))
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs(45)).await;
+        broker
+            .send_message("topic", clock.message(2))
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_secs(30)).await;
+
+        let subscriber = MockSubscriber::new("late", Behaviour::Ok);
+        let backlog = broker
+            .add_subscriber_with(
+                "topic",
+                subscriber,
+                SubscribeOptions::default().replay(Replay::All),
+            )
+            .unwrap();
+        assert_eq!(
+            backlog,
+            vec![MockMessage {
+                id: 2,
+                time: clock.now() - chrono::Duration::try_seconds(30).unwrap()
+            }]
+        );
+
+        clock.advance(Duration::from_secs(31));
+        let backlog = broker
+            .add_subscriber_with(
+                "topic",
+                MockSubscriber::new("later", Behaviour::Ok),
+                SubscribeOptions::default().replay(Replay::All),
+            )
+            .unwrap();
+        assert!(backlog.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remove_subscriber_and_introspection() {
+        let broker = TestBroker::new();
+        broker.create_channel("a").unwrap();
+        broker.create_channel("b").unwrap();
+
+        let first = MockSubscriber::new("first", Behaviour::Ok);
+        let second = MockSubscriber::new("second", Behaviour::Ok);
+        broker.add_subscriber("a", first.clone()).unwrap();
+        broker.add_subscriber("b", first.clone()).unwrap();
+        broker.add_subscriber("b", second.clone()).unwrap();
+
+        let mut channels = broker.list_channels();
+        channels.sort();
+        assert_eq!(channels, vec!["a", "b"]);
+        assert_eq!(broker.subscriber_count("b").unwrap(), 2);
+        let mut channels = broker.channels_for(&first);
+        channels.sort();
+        assert_eq!(channels, vec!["a", "b"]);
+
+        assert!(broker.remove_subscriber("b", &first).unwrap());
+        assert!(!broker.remove_subscriber("b", &first).unwrap());
+        assert_eq!(broker.channels_for(&first), vec!["a"]);
+        assert_eq!(broker.subscriber_count("b").unwrap(), 1);
+        assert!(matches!(
+            broker.subscriber_count("missing"),
+            Err(Error::ChannelDoesNotExist(_))
+        ));
+
+        broker.send_message("b", MockMessage::new(1)).await.unwrap();
+        assert!(first.received().is_empty());
+        assert_eq!(second.received(), vec![1]);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum TestTopic {
+        Room(i32),
+    }
+
+    impl std::fmt::Display for TestTopic {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Room(id) => write!(f, "room.{id}"),
+            }
+        }
+    }
+
+    impl Topic for TestTopic {}
+
+    #[tokio::test]
+    async fn test_typed_topics() {
+        let broker = TestBroker::new();
+        let subscriber = MockSubscriber::new("typed", Behaviour::Ok);
+        broker.create_channel(&TestTopic::Room(7)).unwrap();
+        broker
+            .add_subscriber(&TestTopic::Room(7), subscriber.clone())
+            .unwrap();
+
+        // typed and string topics name the same channels
+        assert_eq!(broker.list_channels(), vec!["room.7"]);
+        broker
+            .send_message(&TestTopic::Room(7), MockMessage::new(1))
+            .await
+            .unwrap();
+        broker
+            .send_message(&"room.7".to_string(), MockMessage::new(2))
+            .await
+            .unwrap();
+        assert!(matches!(
+            broker.subscriber_count(&TestTopic::Room(8)),
+            Err(Error::ChannelDoesNotExist(_))
+        ));
+        assert_eq!(subscriber.received(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_subscriptions() {
+        let broker = TestBroker::new().with_auto_create_channels(true);
+
+        let room = MockSubscriber::new("room", Behaviour::Ok);
+        let typing = MockSubscriber::new("typing", Behaviour::Ok);
+        broker
+            .add_subscriber("room.1.typing", room.clone())
+            .unwrap();
+        broker.add_subscriber("room.>", room.clone()).unwrap();
+        broker
+            .add_subscriber("room.*.typing", typing.clone())
+            .unwrap();
+
+        let report = broker
+            .send_message("room.1.typing", MockMessage::new(1))
+            .await
+            .unwrap();
+        // matched by two patterns, delivered once
+        assert_eq!(report.delivered(), 2);
+        broker
+            .send_message("room.2.typing", MockMessage::new(2))
+            .await
+            .unwrap();
+        broker
+            .send_message("room.2.joined", MockMessage::new(3))
+            .await
+            .unwrap();
+
+        assert_eq!(room.received(), vec![1, 2, 3]);
+        assert_eq!(typing.received(), vec![1, 2]);
+        assert!(matches!(
+            broker
+                .send_message("user.1.inbox", MockMessage::new(4))
+                .await,
+            Err(Error::ChannelDoesNotExist(_))
+        ));
+        assert!(matches!(
+            broker
+                .send_message("room.*.typing", MockMessage::new(5))
+                .await,
+            Err(Error::InvalidTopic(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_requires_channel_unless_auto_created() {
+        let broker = TestBroker::new();
+        let subscriber = MockSubscriber::new("a", Behaviour::Ok);
+
+        assert!(matches!(
+            broker.add_subscriber("user.1.inbox", subscriber.clone()),
+            Err(Error::ChannelDoesNotExist(_))
+        ));
+        assert!(broker.list_channels().is_empty());
+
+        let broker = broker.with_auto_create_channels(true);
+        broker.add_subscriber("user.1.inbox", subscriber).unwrap();
+        assert_eq!(broker.list_channels(), vec!["user.1.inbox"]);
+    }
+
+    fn redelivery(max_attempts: u32) -> RedeliveryPolicy {
+        RedeliveryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_delivery_is_retried_with_backoff() {
+        let broker = TestBroker::new().with_redelivery(redelivery(5));
+        broker.create_channel("topic").unwrap();
+        let flaky = MockSubscriber::new("flaky", Behaviour::FailFirst(2));
+        broker.add_subscriber("topic", flaky.clone()).unwrap();
+
+        let start = tokio::time::Instant::now();
+        let report = broker
+            .send_message("topic", MockMessage::new(1))
+            .await
+            .unwrap();
+        assert_eq!(report.delivered(), 0);
+        // still subscribed while the message is retried
+        assert_eq!(broker.subscriber_count("topic").unwrap(), 1);
+
+        // retried after 1s and then after another 2s
+        tokio::time::sleep(Duration::from_millis(2900)).await;
+        assert!(flaky.received().is_empty());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(flaky.received(), vec![1]);
+        assert_eq!(flaky.attempts(), 3);
+        let gaps: Vec<_> = flaky
+            .attempted_at()
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect();
+        assert_eq!(gaps, vec![Duration::from_secs(1), Duration::from_secs(2)]);
+        assert!(start.elapsed() >= Duration::from_secs(3));
+        assert_eq!(broker.subscriber_count("topic").unwrap(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exhausted_message_goes_to_dead_letter_channel() {
+        let broker = TestBroker::new()
+            .with_redelivery(redelivery(3))
+            .with_auto_create_channels(true);
+        let broken = MockSubscriber::new("broken", Behaviour::Fail);
+        let dead_letters = MockSubscriber::new("dead-letters", Behaviour::Ok);
+        broker
+            .add_subscriber("user.1.inbox", broken.clone())
+            .unwrap();
+        broker
+            .add_subscriber(
+                &redelivery::dead_letter_topic("user.1.inbox"),
+                dead_letters.clone(),
+            )
+            .unwrap();
+
+        broker
+            .send_message("user.1.inbox", MockMessage::new(7))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        assert_eq!(broken.attempts(), 3);
+        assert_eq!(dead_letters.received(), vec![7]);
+        assert_eq!(broker.subscriber_count("user.1.inbox").unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unsubscribing_stops_redelivery() {
+        let broker = TestBroker::new().with_redelivery(redelivery(5));
+        broker.create_channel("topic").unwrap();
+        let broken = MockSubscriber::new("broken", Behaviour::Fail);
+        broker.add_subscriber("topic", broken.clone()).unwrap();
+
+        broker
+            .send_message("topic", MockMessage::new(1))
+            .await
+            .unwrap();
+        broker.remove_subscriber("topic", &broken).unwrap();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+
+        assert_eq!(broken.attempts(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_subscription_pipelines() {
+        let broker = TestBroker::new();
+        broker.create_channel("room.1").unwrap();
+
+        let all = MockSubscriber::new("all", Behaviour::Ok);
+        let even = MockSubscriber::new("even", Behaviour::Ok);
+        let scaled = MockSubscriber::new("scaled", Behaviour::Ok);
+        broker.add_subscriber("room.1", all.clone()).unwrap();
+        broker
+            .add_subscriber_with(
+                "room.1",
+                even.clone(),
+                SubscribeOptions::default()
+                    .pipeline(Pipeline::new().filter(|m: &MockMessage| m.id.is_multiple_of(2))),
+            )
+            .unwrap();
+        broker
+            .add_subscriber_with(
+                "room.1",
+                scaled.clone(),
+                SubscribeOptions::default()
+                    .pipeline(Pipeline::new().map(|m: MockMessage| MockMessage::new(m.id * 10))),
+            )
+            .unwrap();
+
+        let report = broker
+            .send_message("room.1", MockMessage::new(1))
+            .await
+            .unwrap();
+        // filtered out subscribers are not part of the report
+        assert_eq!(report.outcomes.len(), 2);
+        broker
+            .send_message("room.1", MockMessage::new(2))
+            .await
+            .unwrap();
+
+        assert_eq!(all.received(), vec![1, 2]);
+        assert_eq!(even.received(), vec![2]);
+        assert_eq!(scaled.received(), vec![10, 20]);
+
+        // subscribing again replaces the pipeline
+        broker.add_subscriber("room.1", even.clone()).unwrap();
+        broker
+            .send_message("room.1", MockMessage::new(3))
+            .await
+            .unwrap();
+        assert_eq!(even.received(), vec![2, 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stats() {
+        let broker = TestBroker::new()
+            .with_delivery_timeout(Duration::from_secs(1))
+            .with_cache(CacheConfig {
+                max_messages: 10,
+                max_age: None,
+            });
+        broker.create_channel("a").unwrap();
+        broker.create_channel("b").unwrap();
+        let ok = MockSubscriber::new("ok", Behaviour::Slow(Duration::from_millis(200)));
+        broker.add_subscriber("a", ok.clone()).unwrap();
+        broker.add_subscriber("b", ok.clone()).unwrap();
+        broker
+            .add_subscriber("a", MockSubscriber::new("fail", Behaviour::Fail))
+            .unwrap();
+        broker
+            .add_subscriber(
+                "a",
+                MockSubscriber::new("slow", Behaviour::Slow(Duration::from_secs(5))),
+            )
+            .unwrap();
+
+        assert_eq!(broker.stats().subscriptions, 4);
+        broker.send_message("a", MockMessage::new(1)).await.unwrap();
+        broker.send_message("b", MockMessage::new(2)).await.unwrap();
+
+        let stats = broker.stats();
+        assert_eq!(
+            stats,
+            BrokerStats {
+                channels: 2,
+                subscriptions: 2,
+                cached_messages: 2,
+                published: 2,
+                delivered: 2,
+                failed: 1,
+                timed_out: 1,
+                evicted: 2,
+                dead_lettered: 0,
+                // (200ms + 0 + 1s + 200ms) / 4
+                mean_delivery_latency: Duration::from_millis(350),
+                max_delivery_latency: Duration::from_secs(1),
+            }
+        );
+    }
+}
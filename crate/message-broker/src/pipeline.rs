@@ -0,0 +1,148 @@
+//! Per-subscription filters and transforms, applied before a message reaches the subscriber.
+
+use std::{borrow::Cow, sync::Arc};
+
+type FilterFn<M> = Arc<dyn Fn(&M) -> bool + Send + Sync>;
+type MapFn<M> = Arc<dyn Fn(M) -> M + Send + Sync>;
+
+enum Stage<M> {
+    Filter(FilterFn<M>),
+    Map(MapFn<M>),
+}
+
+impl<M> Clone for Stage<M> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Filter(filter) => Self::Filter(filter.clone()),
+            Self::Map(map) => Self::Map(map.clone()),
+        }
+    }
+}
+
+/// Filters and transforms run in the order they were added.
+/// A message rejected by any filter is not delivered to that subscriber at all.
+///
+/// ```
+/// # use message_broker::Pipeline;
+/// // mute one sender and shorten everything else to a preview
+/// let pipeline = Pipeline::new()
+///     .filter(|message: &(u32, String)| message.0 != 7)
+///     .map(|(sender, text)| (sender, text.chars().take(3).collect()));
+///
+/// assert!(pipeline.apply(&(7, "hello".to_string())).is_none());
+/// assert_eq!(
+///     pipeline.apply(&(1, "hello".to_string())).unwrap().1,
+///     "hel"
+/// );
+/// ```
+pub struct Pipeline<M> {
+    stages: Vec<Stage<M>>,
+}
+
+impl<M> Default for Pipeline<M> {
+    fn default() -> Self {
+        Self { stages: Vec::new() }
+    }
+}
+
+impl<M> Clone for Pipeline<M> {
+    fn clone(&self) -> Self {
+        Self {
+            stages: self.stages.clone(),
+        }
+    }
+}
+
+impl<M> std::fmt::Debug for Pipeline<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("stages", &self.stages.len())
+            .finish()
+    }
+}
+
+impl<M> Pipeline<M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops messages for which `predicate` returns `false`.
+    pub fn filter(mut self, predicate: impl Fn(&M) -> bool + Send + Sync + 'static) -> Self {
+        self.stages.push(Stage::Filter(Arc::new(predicate)));
+        self
+    }
+
+    /// Replaces the message with whatever `transform` returns.
+    pub fn map(mut self, transform: impl Fn(M) -> M + Send + Sync + 'static) -> Self {
+        self.stages.push(Stage::Map(Arc::new(transform)));
+        self
+    }
+
+    /// The message the subscriber should get, or `None` if it was filtered out.
+    /// Only clones `message` once a transform needs to own it.
+    pub fn apply<'m>(&self, message: &'m M) -> Option<Cow<'m, M>>
+    where
+        M: Clone,
+    {
+        let mut message = Cow::Borrowed(message);
+        for stage in &self.stages {
+            match stage {
+                Stage::Filter(predicate) => {
+                    if !predicate(&message) {
+                        return None;
+                    }
+                }
+                Stage::Map(transform) => {
+                    message = Cow::Owned(transform(message.into_owned()));
+                }
+            }
+        }
+        Some(message)
+    }
+}
+
+/// Runs `message` through an optional pipeline; no pipeline lets everything through unchanged.
+pub(crate) fn apply<'m, M: Clone>(
+    pipeline: Option<&Pipeline<M>>,
+    message: &'m M,
+) -> Option<Cow<'m, M>> {
+    match pipeline {
+        Some(pipeline) => pipeline.apply(message),
+        None => Some(Cow::Borrowed(message)),
+    }
+}
+
+/// Runs a replayed backlog through the same pipeline as live messages, keeping the order.
+pub(crate) fn apply_all<M: Clone>(pipeline: Option<&Pipeline<M>>, messages: Vec<M>) -> Vec<M> {
+    let Some(pipeline) = pipeline else {
+        return messages;
+    };
+    messages
+        .iter()
+        .filter_map(|message| pipeline.apply(message).map(Cow::into_owned))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stages_run_in_order() {
+        let pipeline = Pipeline::new()
+            .map(|n: u32| n * 10)
+            .filter(|n| *n > 15)
+            .map(|n| n + 1);
+
+        assert!(pipeline.apply(&1).is_none());
+        assert_eq!(pipeline.apply(&2).as_deref(), Some(&21));
+    }
+
+    #[test]
+    fn test_filters_alone_do_not_clone() {
+        let pipeline = Pipeline::new().filter(|n: &u32| n.is_multiple_of(2));
+
+        assert!(matches!(pipeline.apply(&4), Some(Cow::Borrowed(4))));
+        assert!(matches!(apply(None, &3), Some(Cow::Borrowed(3))));
+    }
+}
@@ -0,0 +1,126 @@
+//! At-least-once delivery: a message a subscriber did not acknowledge is handed to it again,
+//! waiting longer after every failure, and ends up on the topic's dead-letter channel once
+//! the attempts run out. A subscriber acknowledges by returning `Ok` from
+//! [`Subscriber::on_message`](crate::Subscriber::on_message).
+//!
+//! Redelivery means a subscriber can see the same message more than once,
+//! consumers filter those out with a [`Deduplicator`].
+
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
+
+/// Prefix of the channels dead letters are published to, see [`dead_letter_topic`].
+pub const DEAD_LETTER_PREFIX: &str = "$dead-letter";
+
+/// The channel messages published to `topic` end up on once every attempt failed.
+/// Like any other channel it has to exist, or be auto created, to receive them.
+pub fn dead_letter_topic(topic: &str) -> String {
+    format!("{DEAD_LETTER_PREFIX}.{topic}")
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RedeliveryPolicy {
+    /// Deliveries per subscriber and message, the first one included.
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled for every attempt after that.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RedeliveryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RedeliveryPolicy {
+    /// How long to wait after the failed `attempt` (starting at 1) before trying again.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Remembers the ids of the most recent messages a consumer has processed.
+///
+/// ```
+/// # use message_broker::Deduplicator;
+/// let mut seen = Deduplicator::new(1024);
+/// assert!(seen.insert("42"));
+/// // redelivered, already handled
+/// assert!(!seen.insert("42"));
+/// ```
+#[derive(Debug)]
+pub struct Deduplicator {
+    capacity: usize,
+    seen: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl Deduplicator {
+    /// Keeps at most `capacity` ids, forgetting the oldest first.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns `true` the first time `id` is seen, like [`HashSet::insert`].
+    pub fn insert(&mut self, id: &str) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if self.seen.contains(id) {
+            return false;
+        }
+
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(id.to_string());
+        self.order.push_back(id.to_string());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_limit() {
+        let policy = RedeliveryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+        };
+
+        let backoffs: Vec<_> = (1..=5).map(|attempt| policy.backoff(attempt)).collect();
+        assert_eq!(backoffs, [1, 2, 4, 5, 5].map(Duration::from_secs).to_vec());
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_deduplicator_forgets_oldest_ids() {
+        let mut seen = Deduplicator::new(2);
+        assert!(seen.insert("a"));
+        assert!(seen.insert("b"));
+        assert!(!seen.insert("a"));
+        assert!(seen.insert("c"));
+        // "a" was pushed out by "c"
+        assert!(seen.insert("a"));
+        assert!(!seen.insert("c"));
+    }
+}
@@ -0,0 +1,383 @@
+//! Request/reply on top of [`BrokerSend`] and [`BrokerReceive`], so every backend has it.
+//!
+//! A request gets a fresh reply inbox topic and a correlation id. The requester
+//! subscribes a [`ReplyInbox`] to the inbox, publishes the request and waits for the
+//! first message carrying its correlation id. Responders answer with [`BrokerRequest::reply`].
+
+use std::{
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+use crate::{BrokerReceive, BrokerSend, DeliveryReport, Error, Message, Subscriber, Topic};
+
+/// First token of every reply inbox topic.
+pub const REPLY_INBOX_PREFIX: &str = "_inbox";
+
+/// A message that can carry where and under which id its reply is expected.
+pub trait RequestMessage<T>: Message<T>
+where
+    T: Send + Sync + Clone,
+{
+    fn reply_to(&self) -> Option<&str>;
+    fn correlation_id(&self) -> Option<&str>;
+    /// Called on requests; replies only get a correlation id.
+    fn set_reply_to(&mut self, reply_to: Option<String>, correlation_id: String);
+}
+
+/// Subscriber standing in for the requester on its reply inbox.
+/// Backends need a subscriber type it converts into, see [`BrokerRequest`].
+pub struct ReplyInbox<M> {
+    correlation_id: String,
+    sender: Arc<Mutex<Option<oneshot::Sender<M>>>>,
+}
+
+impl<M> Clone for ReplyInbox<M> {
+    fn clone(&self) -> Self {
+        Self {
+            correlation_id: self.correlation_id.clone(),
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<M> std::fmt::Debug for ReplyInbox<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplyInbox")
+            .field("correlation_id", &self.correlation_id)
+            .finish()
+    }
+}
+
+impl<M> PartialEq for ReplyInbox<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.correlation_id == other.correlation_id
+    }
+}
+
+impl<M> Eq for ReplyInbox<M> {}
+
+impl<M> Hash for ReplyInbox<M> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.correlation_id.hash(state);
+    }
+}
+
+impl<M> ReplyInbox<M> {
+    pub fn correlation_id(&self) -> &str {
+        &self.correlation_id
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, M> Subscriber<T, M> for ReplyInbox<M>
+where
+    T: Send + Sync + Clone,
+    M: RequestMessage<T> + Clone + Send + Sync,
+{
+    /// Takes the first message with the right correlation id, anything else is ignored.
+    async fn on_message(&self, message: &M) -> Result<(), Error> {
+        if message.correlation_id() != Some(self.correlation_id.as_str()) {
+            return Ok(());
+        }
+        if let Some(sender) = self.sender.lock().take() {
+            let _ = sender.send(message.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Unique within the process, and across processes thanks to the pid and start time.
+fn next_correlation_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    static PROCESS: OnceLock<String> = OnceLock::new();
+
+    let process = PROCESS.get_or_init(|| {
+        let started = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        format!("{:x}-{:x}", std::process::id(), started)
+    });
+    format!("{process}-{:x}", COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Runs the closure when dropped.
+struct OnDrop<F: FnMut()>(F);
+
+impl<F: FnMut()> Drop for OnDrop<F> {
+    fn drop(&mut self) {
+        (self.0)()
+    }
+}
+
+/// Request/reply for every broker whose subscriber type can stand in for a [`ReplyInbox`].
+///
+/// With a networked backend the inbox subscription may reach the server after
+/// the request, a very fast responder can then reply into the void and the request times out.
+#[async_trait::async_trait]
+pub trait BrokerRequest<T, S, M>: BrokerSend<T, S, M> + BrokerReceive<T, S, M> + Sync
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + From<ReplyInbox<M>> + Send,
+    M: RequestMessage<T> + Send + Sync + 'static,
+{
+    /// Publishes `message` to `topic` and waits up to `timeout` for the reply.
+    /// Fails with [`Error::NoResponders`] if nobody received the request.
+    async fn request<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        mut message: M,
+        timeout: Duration,
+    ) -> Result<M, Error> {
+        let topic: &str = &topic.topic();
+        let correlation_id = next_correlation_id();
+        let inbox = format!("{REPLY_INBOX_PREFIX}.{correlation_id}");
+        let (sender, receiver) = oneshot::channel();
+        let reply_inbox = ReplyInbox {
+            correlation_id: correlation_id.clone(),
+            sender: Arc::new(Mutex::new(Some(sender))),
+        };
+
+        match self.create_channel(&inbox) {
+            Ok(()) | Err(Error::ChannelAlreadyExist(_)) => {}
+            Err(err) => return Err(err),
+        }
+        // also runs when subscribing fails or the caller drops this future
+        let _cleanup = OnDrop(|| {
+            let _ = self.delete_channel(&inbox);
+        });
+        self.add_subscriber(&inbox, S::from(reply_inbox))?;
+        message.set_reply_to(Some(inbox.clone()), correlation_id);
+
+        async {
+            let report = self.send_message(topic, message).await?;
+            if report.delivered() == 0 && report.remote_receivers == 0 {
+                return Err(Error::NoResponders(topic.to_string()));
+            }
+            match tokio::time::timeout(timeout, receiver).await {
+                Ok(Ok(reply)) => Ok(reply),
+                Ok(Err(_)) => Err(Error::Other("reply inbox dropped".to_string())),
+                Err(_) => Err(Error::Timeout(topic.to_string())),
+            }
+        }
+        .await
+    }
+
+    /// Sends `reply` to the inbox `request` asked for.
+    async fn reply(&self, request: &M, mut reply: M) -> Result<DeliveryReport<S>, Error> {
+        let (Some(reply_to), Some(correlation_id)) = (request.reply_to(), request.correlation_id())
+        else {
+            return Err(Error::Other(format!(
+                "message {} does not expect a reply",
+                request.id()
+            )));
+        };
+        reply.set_reply_to(None, correlation_id.to_string());
+
+        self.send_message(reply_to, reply).await
+    }
+}
+
+impl<B, T, S, M> BrokerRequest<T, S, M> for B
+where
+    B: BrokerSend<T, S, M> + BrokerReceive<T, S, M> + Sync,
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + From<ReplyInbox<M>> + Send,
+    M: RequestMessage<T> + Send + Sync + 'static,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryBroker;
+
+    type TestBroker = InMemoryBroker<u32, TestSubscriber, TestRequest>;
+
+    #[derive(Debug, Clone)]
+    struct TestRequest {
+        value: u32,
+        reply_to: Option<String>,
+        correlation_id: Option<String>,
+    }
+
+    impl TestRequest {
+        fn new(value: u32) -> Self {
+            Self {
+                value,
+                reply_to: None,
+                correlation_id: None,
+            }
+        }
+    }
+
+    impl Message<u32> for TestRequest {
+        fn id(&self) -> String {
+            self.value.to_string()
+        }
+
+        fn time(&self) -> chrono::DateTime<chrono::Utc> {
+            chrono::Utc::now()
+        }
+
+        fn payload(&self) -> &u32 {
+            &self.value
+        }
+    }
+
+    impl RequestMessage<u32> for TestRequest {
+        fn reply_to(&self) -> Option<&str> {
+            self.reply_to.as_deref()
+        }
+
+        fn correlation_id(&self) -> Option<&str> {
+            self.correlation_id.as_deref()
+        }
+
+        fn set_reply_to(&mut self, reply_to: Option<String>, correlation_id: String) {
+            self.reply_to = reply_to;
+            self.correlation_id = Some(correlation_id);
+        }
+    }
+
+    /// Doubles every request it gets, or ignores it when silent.
+    #[derive(Clone)]
+    enum TestSubscriber {
+        Doubler(TestBroker),
+        Silent,
+        Inbox(ReplyInbox<TestRequest>),
+    }
+
+    impl From<ReplyInbox<TestRequest>> for TestSubscriber {
+        fn from(inbox: ReplyInbox<TestRequest>) -> Self {
+            Self::Inbox(inbox)
+        }
+    }
+
+    impl PartialEq for TestSubscriber {
+        fn eq(&self, other: &Self) -> bool {
+            match (self, other) {
+                (Self::Inbox(a), Self::Inbox(b)) => a == b,
+                (Self::Doubler(_), Self::Doubler(_)) | (Self::Silent, Self::Silent) => true,
+                _ => false,
+            }
+        }
+    }
+
+    impl Eq for TestSubscriber {}
+
+    impl Hash for TestSubscriber {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            std::mem::discriminant(self).hash(state);
+            if let Self::Inbox(inbox) = self {
+                inbox.hash(state);
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Subscriber<u32, TestRequest> for TestSubscriber {
+        async fn on_message(&self, message: &TestRequest) -> Result<(), Error> {
+            match self {
+                Self::Doubler(broker) => {
+                    broker
+                        .reply(message, TestRequest::new(message.value * 2))
+                        .await?;
+                    Ok(())
+                }
+                Self::Silent => Ok(()),
+                Self::Inbox(inbox) => inbox.on_message(message).await,
+            }
+        }
+    }
+
+    fn broker() -> TestBroker {
+        let broker = TestBroker::default();
+        broker.create_channel("math.double").unwrap();
+        broker
+    }
+
+    #[tokio::test]
+    async fn request_gets_reply_and_cleans_up_inbox() {
+        let broker = broker();
+        broker
+            .add_subscriber("math.double", TestSubscriber::Doubler(broker.clone()))
+            .unwrap();
+
+        let reply = broker
+            .request("math.double", TestRequest::new(21), Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        assert_eq!(reply.value, 42);
+        assert!(reply.reply_to.is_none());
+        assert!(reply.correlation_id.is_some());
+        assert_eq!(broker.list_channels(), vec!["math.double".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn request_times_out_without_reply() {
+        let broker = broker();
+        broker
+            .add_subscriber("math.double", TestSubscriber::Silent)
+            .unwrap();
+
+        let result = broker
+            .request(
+                "math.double",
+                TestRequest::new(1),
+                Duration::from_millis(20),
+            )
+            .await;
+
+        assert!(matches!(result, Err(Error::Timeout(_))));
+        assert_eq!(broker.list_channels(), vec!["math.double".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn dropped_request_cleans_up_inbox() {
+        let broker = broker();
+        broker
+            .add_subscriber("math.double", TestSubscriber::Silent)
+            .unwrap();
+
+        let request = broker.request("math.double", TestRequest::new(1), Duration::from_secs(10));
+        // gives up long before the request would time out
+        let result = tokio::time::timeout(Duration::from_millis(20), request).await;
+
+        assert!(result.is_err());
+        assert_eq!(broker.list_channels(), vec!["math.double".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn request_without_responders_fails_fast() {
+        let broker = broker();
+
+        let result = broker
+            .request("math.double", TestRequest::new(1), Duration::from_secs(10))
+            .await;
+
+        assert!(matches!(result, Err(Error::NoResponders(_))));
+    }
+
+    #[tokio::test]
+    async fn reply_to_plain_message_is_rejected() {
+        let broker = broker();
+
+        let result = broker
+            .reply(&TestRequest::new(1), TestRequest::new(2))
+            .await;
+
+        assert!(matches!(result, Err(Error::Other(_))));
+    }
+
+    #[test]
+    fn correlation_ids_are_unique() {
+        assert_ne!(next_correlation_id(), next_correlation_id());
+    }
+}
@@ -0,0 +1,558 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    hash::Hash,
+    io,
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::RwLock;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::mpsc,
+};
+
+use super::{
+    codec::{read_value, Value},
+    RedisSubscription,
+};
+use crate::{
+    delivery, pipeline,
+    topic::{self, TopicTrie},
+    BrokerReceive, BrokerSend, DeliveryReport, Error, Message, Pipeline, SubscribeOptions,
+    Subscriber, Topic,
+};
+
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct RespConfig {
+    /// How long a single local subscriber may take to accept a message before it is evicted.
+    pub delivery_timeout: Duration,
+    /// Lets `add_subscriber` create a missing channel instead of failing.
+    /// Such a channel is deleted again, and unsubscribed on the server,
+    /// once its last local subscriber is removed.
+    pub auto_create_channels: bool,
+}
+
+impl Default for RespConfig {
+    fn default() -> Self {
+        Self {
+            delivery_timeout: Duration::from_secs(5),
+            auto_create_channels: false,
+        }
+    }
+}
+
+struct Channel<S, M> {
+    subscribers: HashMap<S, Option<Pipeline<M>>>,
+    /// Created by `add_subscriber`, lives only as long as it has subscribers.
+    auto_created: bool,
+}
+
+impl<S, M> Default for Channel<S, M> {
+    fn default() -> Self {
+        Self {
+            subscribers: HashMap::new(),
+            auto_created: false,
+        }
+    }
+}
+
+struct Shared<S, M> {
+    addr: String,
+    config: RespConfig,
+    channels: RwLock<TopicTrie<Channel<S, M>>>,
+}
+
+impl<S, M> Shared<S, M> {
+    /// Every Redis subscription the local channels need.
+    fn subscriptions(&self) -> HashSet<RedisSubscription> {
+        self.channels
+            .read()
+            .iter()
+            .into_iter()
+            .map(|(pattern, _)| RedisSubscription::for_pattern(&pattern))
+            .collect()
+    }
+}
+
+/// Publishes through a Redis compatible server and delivers what the server pushes back
+/// to the local subscribers, so every process connected to the same server shares topics.
+///
+/// Uses two connections: commands such as `PUBLISH` cannot be sent on a connection
+/// in subscribe mode. The subscribe connection reconnects on its own and restores every
+/// subscription; messages published while it is down are lost, as with plain Redis pub/sub.
+/// Subscribing is sent in the background, so a message published right after
+/// `add_subscriber` returns may still miss the new subscriber.
+///
+/// Messages travel as JSON. A subscriber on overlapping patterns that map to different
+/// Redis subscriptions, e.g. `room.1` and `room.*`, may receive a message once per pattern.
+pub struct RespBroker<MsgInner, Sub, Msg>
+where
+    MsgInner: Send + Sync + Clone,
+    Sub: Subscriber<MsgInner, Msg> + Hash + Eq,
+    Msg: Message<MsgInner> + Serialize + DeserializeOwned,
+{
+    shared: Arc<Shared<Sub, Msg>>,
+    commands: mpsc::UnboundedSender<Value>,
+    publisher: Arc<tokio::sync::Mutex<Option<Publisher>>>,
+    _phantom: std::marker::PhantomData<MsgInner>,
+}
+
+impl<T, S, M> Clone for RespBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Hash + Eq,
+    M: Message<T> + Serialize + DeserializeOwned,
+{
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            commands: self.commands.clone(),
+            publisher: self.publisher.clone(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T, S, M> RespBroker<T, S, M>
+where
+    T: Send + Sync + Clone + 'static,
+    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq + 'static,
+    M: Message<T> + Serialize + DeserializeOwned + Send + Sync + Clone + 'static,
+{
+    /// Connects to `addr`, given as `host:port` or `redis://host:port`.
+    /// Fails if the server cannot be reached now; later disconnects are retried.
+    pub async fn connect(addr: &str, config: RespConfig) -> Result<Self, Error> {
+        let addr = addr.strip_prefix("redis://").unwrap_or(addr).to_string();
+        let publisher = Publisher::connect(&addr).await.map_err(connection_error)?;
+        let subscriber = TcpStream::connect(&addr).await.map_err(connection_error)?;
+
+        let shared = Arc::new(Shared {
+            addr,
+            config,
+            channels: RwLock::new(TopicTrie::default()),
+        });
+        let (commands, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run_subscriptions::<T, S, M>(
+            shared.clone(),
+            receiver,
+            subscriber,
+        ));
+
+        Ok(Self {
+            shared,
+            commands,
+            publisher: Arc::new(tokio::sync::Mutex::new(Some(publisher))),
+            _phantom: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<T, S, M> RespBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Hash + Eq,
+    M: Message<T> + Serialize + DeserializeOwned,
+{
+    /// Queues a (P)SUBSCRIBE or (P)UNSUBSCRIBE for the subscribe connection.
+    fn send_subscription(&self, subscription: &RedisSubscription, subscribe: bool) {
+        let command = subscription_command(subscription, subscribe);
+        // the receiver only goes away together with the last broker handle
+        let _ = self.commands.send(command);
+    }
+
+    /// Creates the channel under an already held lock and subscribes on the server
+    /// unless another channel already needs the same subscription.
+    fn create_locked(
+        &self,
+        channels: &mut TopicTrie<Channel<S, M>>,
+        pattern: &str,
+        auto_created: bool,
+    ) {
+        let subscription = RedisSubscription::for_pattern(pattern);
+        let already_subscribed = channels
+            .iter()
+            .into_iter()
+            .any(|(other, _)| RedisSubscription::for_pattern(&other) == subscription);
+        *channels.slot(pattern) = Some(Channel {
+            auto_created,
+            ..Channel::default()
+        });
+        if !already_subscribed {
+            self.send_subscription(&subscription, true);
+        }
+    }
+
+    /// Deletes the channel under an already held lock and unsubscribes on the server
+    /// unless another channel still needs the same subscription.
+    fn delete_locked(&self, channels: &mut TopicTrie<Channel<S, M>>, pattern: &str) -> bool {
+        if channels.remove(pattern).is_none() {
+            return false;
+        }
+
+        let subscription = RedisSubscription::for_pattern(pattern);
+        let still_needed = channels
+            .iter()
+            .into_iter()
+            .any(|(other, _)| RedisSubscription::for_pattern(&other) == subscription);
+        if !still_needed {
+            self.send_subscription(&subscription, false);
+        }
+        true
+    }
+}
+
+impl<T, S, M> BrokerReceive<T, S, M> for RespBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq,
+    M: Message<T> + Serialize + DeserializeOwned + Send + Sync + Clone,
+{
+    fn create_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), Error> {
+        let topic: &str = &topic.topic();
+        topic::validate_pattern(topic)?;
+
+        let mut channels = self.shared.channels.write();
+        if channels.get(topic).is_some() {
+            return Err(Error::ChannelAlreadyExist(topic.to_string()));
+        }
+        self.create_locked(&mut channels, topic, false);
+
+        Ok(())
+    }
+
+    fn delete_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), Error> {
+        let topic: &str = &topic.topic();
+        if !self.delete_locked(&mut self.shared.channels.write(), topic) {
+            return Err(Error::ChannelDoesNotExist(topic.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn add_subscriber<K: Topic + ?Sized>(&self, topic: &K, subscriber: S) -> Result<(), Error> {
+        self.add_subscriber_with(topic, subscriber, SubscribeOptions::default())?;
+
+        Ok(())
+    }
+
+    fn remove_subscriber<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: &S,
+    ) -> Result<bool, Error> {
+        let topic: &str = &topic.topic();
+        let mut channels = self.shared.channels.write();
+        let channel = channels
+            .get_mut(topic)
+            .ok_or(Error::ChannelDoesNotExist(topic.to_string()))?;
+        let removed = channel.subscribers.remove(subscriber).is_some();
+
+        // otherwise the server keeps pushing, and counting us as a receiver
+        if channel.auto_created && channel.subscribers.is_empty() {
+            self.delete_locked(&mut channels, topic);
+        }
+
+        Ok(removed)
+    }
+
+    fn list_channels(&self) -> Vec<String> {
+        self.shared
+            .channels
+            .read()
+            .iter()
+            .into_iter()
+            .map(|(topic, _)| topic)
+            .collect()
+    }
+
+    fn subscriber_count<K: Topic + ?Sized>(&self, topic: &K) -> Result<usize, Error> {
+        let topic: &str = &topic.topic();
+        let count = self
+            .shared
+            .channels
+            .read()
+            .get(topic)
+            .ok_or(Error::ChannelDoesNotExist(topic.to_string()))?
+            .subscribers
+            .len();
+
+        Ok(count)
+    }
+
+    fn channels_for(&self, subscriber: &S) -> Vec<String> {
+        self.shared
+            .channels
+            .read()
+            .iter()
+            .into_iter()
+            .filter(|(_, channel)| channel.subscribers.contains_key(subscriber))
+            .map(|(topic, _)| topic)
+            .collect()
+    }
+
+    /// Redis keeps no history, nothing is ever replayed.
+    fn add_subscriber_with<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: S,
+        options: SubscribeOptions<M>,
+    ) -> Result<Vec<M>, Error> {
+        let topic: &str = &topic.topic();
+        topic::validate_pattern(topic)?;
+
+        let mut channels = self.shared.channels.write();
+        if channels.get(topic).is_none() {
+            if !self.shared.config.auto_create_channels {
+                return Err(Error::ChannelDoesNotExist(topic.to_string()));
+            }
+            self.create_locked(&mut channels, topic, true);
+        }
+        channels
+            .get_mut(topic)
+            .expect("channel was just ensured")
+            .subscribers
+            .insert(subscriber, options.pipeline);
+
+        Ok(Vec::new())
+    }
+}
+
+#[async_trait::async_trait]
+impl<T, S, M> BrokerSend<T, S, M> for RespBroker<T, S, M>
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq,
+    M: Message<T> + Serialize + DeserializeOwned + Send + Sync + Clone,
+{
+    /// Publishes on the server. Local subscribers get the message when the server
+    /// pushes it back, so the report only carries [`DeliveryReport::remote_receivers`].
+    async fn send_message<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        message: M,
+    ) -> Result<DeliveryReport<S>, Error> {
+        let topic: &str = &topic.topic();
+        topic::validate_topic(topic)?;
+        let payload = serde_json::to_vec(&message).map_err(|err| Error::Other(err.to_string()))?;
+
+        let mut publisher = self.publisher.lock().await;
+        // one retry on a fresh connection, the old one may have been dropped by the server
+        let mut reply = None;
+        for _ in 0..2 {
+            if publisher.is_none() {
+                *publisher = Publisher::connect(&self.shared.addr).await.ok();
+            }
+            let Some(connection) = publisher.as_mut() else {
+                continue;
+            };
+            match connection.publish(topic, &payload).await {
+                Ok(value) => {
+                    reply = Some(value);
+                    break;
+                }
+                Err(_) => *publisher = None,
+            }
+        }
+
+        match reply {
+            Some(Value::Integer(receivers)) => Ok(DeliveryReport {
+                remote_receivers: usize::try_from(receivers).unwrap_or_default(),
+                ..DeliveryReport::default()
+            }),
+            Some(Value::Error(err)) => Err(Error::Other(err)),
+            Some(other) => Err(Error::Other(format!("unexpected PUBLISH reply: {other:?}"))),
+            None => Err(Error::Other(format!(
+                "cannot reach broker at {}",
+                self.shared.addr
+            ))),
+        }
+    }
+}
+
+/// The connection used for commands.
+struct Publisher {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Publisher {
+    async fn connect(addr: &str) -> io::Result<Self> {
+        let (read, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Self {
+            reader: BufReader::new(read),
+            writer,
+        })
+    }
+
+    async fn publish(&mut self, topic: &str, payload: &[u8]) -> io::Result<Value> {
+        let command = Value::command([&b"PUBLISH"[..], topic.as_bytes(), payload]);
+        self.writer.write_all(&command.to_bytes()).await?;
+        read_value(&mut self.reader)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "broker closed"))
+    }
+}
+
+fn subscription_command(subscription: &RedisSubscription, subscribe: bool) -> Value {
+    let (command, name) = match (subscription, subscribe) {
+        (RedisSubscription::Channel(name), true) => ("SUBSCRIBE", name),
+        (RedisSubscription::Channel(name), false) => ("UNSUBSCRIBE", name),
+        (RedisSubscription::Pattern(glob), true) => ("PSUBSCRIBE", glob),
+        (RedisSubscription::Pattern(glob), false) => ("PUNSUBSCRIBE", glob),
+    };
+    Value::command([command.as_bytes(), name.as_bytes()])
+}
+
+fn connection_error(err: io::Error) -> Error {
+    Error::Other(format!("broker connection failed: {err}"))
+}
+
+/// Owns the subscribe connection: forwards queued (un)subscribe commands,
+/// and reconnects with backoff, restoring every subscription, whenever it drops.
+/// Ends once every broker handle is gone.
+async fn run_subscriptions<T, S, M>(
+    shared: Arc<Shared<S, M>>,
+    mut commands: mpsc::UnboundedReceiver<Value>,
+    stream: TcpStream,
+) where
+    T: Send + Sync + Clone + 'static,
+    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq + 'static,
+    M: Message<T> + DeserializeOwned + Send + Sync + Clone + 'static,
+{
+    let mut stream = Some(stream);
+    let mut backoff = INITIAL_RECONNECT_BACKOFF;
+
+    loop {
+        let connection = match stream.take() {
+            Some(stream) => Ok(stream),
+            None => TcpStream::connect(&shared.addr).await,
+        };
+        let Ok(connection) = connection else {
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                // commands issued while disconnected are covered by the resubscribe below
+                command = commands.recv() => if command.is_none() { return },
+            }
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+            continue;
+        };
+        backoff = INITIAL_RECONNECT_BACKOFF;
+
+        let (read, mut write) = connection.into_split();
+        let mut reader = tokio::spawn(dispatch_pushes::<T, S, M>(shared.clone(), read));
+
+        let mut resubscribe = Vec::new();
+        for subscription in shared.subscriptions() {
+            subscription_command(&subscription, true).encode(&mut resubscribe);
+        }
+        if write.write_all(&resubscribe).await.is_ok() {
+            loop {
+                tokio::select! {
+                    command = commands.recv() => {
+                        let Some(command) = command else {
+                            reader.abort();
+                            return;
+                        };
+                        if write.write_all(&command.to_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    _ = &mut reader => break,
+                }
+            }
+        }
+        reader.abort();
+    }
+}
+
+/// Reads what the server pushes on the subscribe connection until it closes.
+async fn dispatch_pushes<T, S, M>(shared: Arc<Shared<S, M>>, read: OwnedReadHalf)
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq,
+    M: Message<T> + DeserializeOwned + Send + Sync + Clone,
+{
+    let mut reader = BufReader::new(read);
+    while let Ok(Some(value)) = read_value(&mut reader).await {
+        let Value::Array(parts) = value else {
+            continue;
+        };
+        let parts: Option<Vec<&[u8]>> = parts.iter().map(Value::as_bytes).collect();
+        match parts.as_deref() {
+            Some([b"message", topic, payload]) => {
+                dispatch::<T, S, M>(&shared, None, topic, payload).await;
+            }
+            Some([b"pmessage", glob, topic, payload]) => {
+                dispatch::<T, S, M>(&shared, Some(glob), topic, payload).await;
+            }
+            // subscription confirmations
+            _ => {}
+        }
+    }
+}
+
+/// Hands one pushed message to the local subscribers of the channels behind the
+/// Redis subscription it arrived on.
+async fn dispatch<T, S, M>(shared: &Shared<S, M>, glob: Option<&[u8]>, topic: &[u8], payload: &[u8])
+where
+    T: Send + Sync + Clone,
+    S: Subscriber<T, M> + Send + Sync + Clone + Hash + Eq,
+    M: Message<T> + DeserializeOwned + Send + Sync + Clone,
+{
+    let (Ok(topic), Ok(message)) = (
+        std::str::from_utf8(topic),
+        serde_json::from_slice::<M>(payload),
+    ) else {
+        return;
+    };
+    if topic::validate_topic(topic).is_err() {
+        return;
+    }
+    let arrived_on = match glob {
+        None => RedisSubscription::Channel(topic.to_string()),
+        Some(glob) => RedisSubscription::Pattern(String::from_utf8_lossy(glob).into_owned()),
+    };
+
+    let mut matched = Vec::new();
+    let mut subscribers = HashMap::new();
+    shared
+        .channels
+        .write()
+        .for_each_match_mut(topic, |pattern, channel| {
+            if RedisSubscription::for_pattern(pattern) != arrived_on {
+                return;
+            }
+            matched.push(pattern.to_string());
+            for (subscriber, pipeline) in &channel.subscribers {
+                if let Entry::Vacant(entry) = subscribers.entry(subscriber.clone()) {
+                    entry.insert(pipeline.clone());
+                }
+            }
+        });
+
+    let deliveries = subscribers
+        .into_iter()
+        .filter_map(|(subscriber, pipeline)| {
+            let message = pipeline::apply(pipeline.as_ref(), &message)?;
+            Some((subscriber, message))
+        });
+    let report = delivery::deliver(deliveries, shared.config.delivery_timeout, None).await;
+
+    let mut channels = shared.channels.write();
+    for pattern in &matched {
+        if let Some(channel) = channels.get_mut(pattern) {
+            for subscriber in report.undelivered() {
+                channel.subscribers.remove(subscriber);
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! RESP2 framing, just enough of it for pub/sub.
+
+use std::{future::Future, io, pin::Pin};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Bulk strings above this size are refused instead of being buffered.
+const MAX_BULK_LEN: usize = 64 * 1024 * 1024;
+/// Arrays above this many elements are refused.
+const MAX_ARRAY_LEN: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    /// The null bulk string or null array.
+    Null,
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// A command as clients send it: an array of bulk strings.
+    pub(crate) fn command<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        Self::Array(
+            parts
+                .into_iter()
+                .map(|part| Self::Bulk(part.to_vec()))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn bulk(data: impl Into<Vec<u8>>) -> Self {
+        Self::Bulk(data.into())
+    }
+
+    pub(crate) fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bulk(data) => Some(data),
+            Self::Simple(text) => Some(text.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Simple(text) => {
+                out.push(b'+');
+                out.extend_from_slice(text.as_bytes());
+            }
+            Self::Error(text) => {
+                out.push(b'-');
+                out.extend_from_slice(text.as_bytes());
+            }
+            Self::Integer(n) => {
+                out.push(b':');
+                out.extend_from_slice(n.to_string().as_bytes());
+            }
+            Self::Bulk(data) => {
+                out.push(b'$');
+                out.extend_from_slice(data.len().to_string().as_bytes());
+                out.extend_from_slice(b"\r\n");
+                out.extend_from_slice(data);
+            }
+            Self::Null => out.extend_from_slice(b"$-1"),
+            Self::Array(values) => {
+                out.push(b'*');
+                out.extend_from_slice(values.len().to_string().as_bytes());
+                out.extend_from_slice(b"\r\n");
+                for value in values {
+                    value.encode(out);
+                }
+                // every element already ended its own line
+                return;
+            }
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+}
+
+/// Reads the next value, `None` on a clean end of stream.
+///
+/// Not cancel safe: dropping the future halfway through a value loses the part already read.
+pub(crate) fn read_value<'a, R>(
+    reader: &'a mut R,
+) -> Pin<Box<dyn Future<Output = io::Result<Option<Value>>> + Send + 'a>>
+where
+    R: AsyncBufRead + Unpin + Send,
+{
+    Box::pin(async move {
+        let mut line = Vec::new();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(None);
+        }
+        let Some(line) = line.strip_suffix(b"\r\n") else {
+            return Err(invalid("line not terminated by CRLF"));
+        };
+        let Some((&kind, rest)) = line.split_first() else {
+            return Err(invalid("empty line"));
+        };
+        let text = || String::from_utf8_lossy(rest).into_owned();
+
+        let value = match kind {
+            b'+' => Value::Simple(text()),
+            b'-' => Value::Error(text()),
+            b':' => Value::Integer(parse_int(rest)?),
+            b'$' => {
+                let len = parse_int(rest)?;
+                if len < 0 {
+                    return Ok(Some(Value::Null));
+                }
+                let len = usize::try_from(len).map_err(|_| invalid("bulk length"))?;
+                if len > MAX_BULK_LEN {
+                    return Err(invalid("bulk string too large"));
+                }
+                let mut data = vec![0; len + 2];
+                reader.read_exact(&mut data).await?;
+                if !data.ends_with(b"\r\n") {
+                    return Err(invalid("bulk string not terminated by CRLF"));
+                }
+                data.truncate(len);
+                Value::Bulk(data)
+            }
+            b'*' => {
+                let len = parse_int(rest)?;
+                if len < 0 {
+                    return Ok(Some(Value::Null));
+                }
+                let len = usize::try_from(len).map_err(|_| invalid("array length"))?;
+                if len > MAX_ARRAY_LEN {
+                    return Err(invalid("array too large"));
+                }
+                let mut values = Vec::with_capacity(len.min(64));
+                for _ in 0..len {
+                    let value = read_value(reader)
+                        .await?
+                        .ok_or_else(|| invalid("stream ended inside an array"))?;
+                    values.push(value);
+                }
+                Value::Array(values)
+            }
+            _ => return Err(invalid("unknown value type")),
+        };
+
+        Ok(Some(value))
+    })
+}
+
+fn parse_int(text: &[u8]) -> io::Result<i64> {
+    std::str::from_utf8(text)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| invalid("malformed integer"))
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid RESP: {reason}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn decode(mut data: &[u8]) -> io::Result<Option<Value>> {
+        read_value(&mut data).await
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let value = Value::Array(vec![
+            Value::bulk("message"),
+            Value::Simple("OK".into()),
+            Value::Error("ERR nope".into()),
+            Value::Integer(-3),
+            Value::Null,
+            Value::Array(vec![Value::bulk(&b"a\r\nb"[..])]),
+        ]);
+
+        let bytes = value.to_bytes();
+        assert_eq!(decode(&bytes).await.unwrap(), Some(value));
+        assert_eq!(decode(b"").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_malformed_input() {
+        assert!(decode(b"$3\r\nab\r\n").await.is_err());
+        assert!(decode(b"*2\r\n:1\r\n").await.is_err());
+        assert!(decode(b":x\r\n").await.is_err());
+        assert!(decode(b"+OK\n").await.is_err());
+        assert!(decode(b"$999999999999\r\n").await.is_err());
+    }
+}
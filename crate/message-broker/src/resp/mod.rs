@@ -0,0 +1,328 @@
+//! A networked backend speaking the Redis pub/sub protocol (RESP2), so several
+//! processes can share topics, plus a small stand-alone server for the same protocol.
+//!
+//! [`RespBroker`] keeps subscribers local and mirrors every channel as a Redis
+//! subscription: concrete topics with `SUBSCRIBE`, wildcard patterns with `PSUBSCRIBE`
+//! and a glob that may match more than the pattern, the broker filters the rest out.
+//! Works against a real Redis or [`RespServer`].
+
+mod client;
+mod codec;
+mod server;
+
+pub use client::{RespBroker, RespConfig};
+pub use server::RespServer;
+
+/// Where a channel pattern is subscribed on the Redis side.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RedisSubscription {
+    Channel(String),
+    Pattern(String),
+}
+
+impl RedisSubscription {
+    fn for_pattern(pattern: &str) -> Self {
+        let tokens: Vec<&str> = pattern.split('.').collect();
+        if !tokens.iter().any(|token| *token == "*" || *token == ">") {
+            return Self::Channel(pattern.to_string());
+        }
+
+        let glob = tokens
+            .iter()
+            .map(|token| match *token {
+                "*" | ">" => "*".to_string(),
+                literal => escape_glob(literal),
+            })
+            .collect::<Vec<_>>()
+            .join(".");
+        Self::Pattern(glob)
+    }
+}
+
+fn escape_glob(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Redis style glob matching: `*`, `?`, `[abc]`, `[^a-z]` and `\` escapes.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // where to resume after the last `*` if the current attempt fails
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(pattern, p, text[t]),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(p + 2),
+            Some(&c) => (c == text[t]).then_some(p + 1),
+            None => None,
+        };
+
+        match (step, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                t += 1;
+            }
+            (None, Some((star, start))) => {
+                // let the last `*` swallow one more byte
+                p = star + 1;
+                t = start + 1;
+                backtrack = Some((star, start + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the class starting at `pattern[start] == b'['`,
+/// returning the position after the class on a match.
+fn match_class(pattern: &[u8], start: usize, c: u8) -> Option<usize> {
+    let mut p = start + 1;
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+    while let Some(&current) = pattern.get(p) {
+        match current {
+            b']' => return (matched != negate).then_some(p + 1),
+            b'\\' if p + 1 < pattern.len() => {
+                matched |= pattern[p + 1] == c;
+                p += 2;
+            }
+            _ if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() => {
+                let (low, high) = (current.min(pattern[p + 2]), current.max(pattern[p + 2]));
+                matched |= (low..=high).contains(&c);
+                p += 3;
+            }
+            _ => {
+                matched |= current == c;
+                p += 1;
+            }
+        }
+    }
+
+    // an unterminated class matches like Redis does: up to the end of the pattern
+    (matched != negate).then_some(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        testing::{Behaviour, MockMessage, MockSubscriber},
+        BrokerReceive, BrokerSend,
+    };
+
+    type TestBroker = RespBroker<u32, MockSubscriber, MockMessage>;
+
+    async fn connect(addr: std::net::SocketAddr) -> TestBroker {
+        let config = RespConfig {
+            auto_create_channels: true,
+            ..RespConfig::default()
+        };
+        TestBroker::connect(&format!("redis://{addr}"), config)
+            .await
+            .unwrap()
+    }
+
+    /// Subscribing and delivery happen in the background, poll until `condition` holds.
+    async fn wait_until(mut condition: impl FnMut() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("condition never held");
+    }
+
+    #[tokio::test]
+    async fn test_instances_share_topics() {
+        let server = RespServer::new();
+        let addr = server
+            .clone()
+            .spawn(([127, 0, 0, 1], 0).into())
+            .await
+            .unwrap();
+        let first = connect(addr).await;
+        let second = connect(addr).await;
+
+        let inbox = MockSubscriber::new("inbox", Behaviour::Ok);
+        let rooms = MockSubscriber::new("rooms", Behaviour::Ok);
+        let typing = MockSubscriber::new("typing", Behaviour::Ok);
+        first.add_subscriber("user.1.inbox", inbox.clone()).unwrap();
+        first.add_subscriber("room.>", rooms.clone()).unwrap();
+        second
+            .add_subscriber("room.*.typing", typing.clone())
+            .unwrap();
+        wait_until(|| server.subscription_count() == 3).await;
+
+        let report = second
+            .send_message("user.1.inbox", MockMessage::new(1))
+            .await
+            .unwrap();
+        assert_eq!(report.remote_receivers, 1);
+        assert_eq!(report.delivered(), 0);
+        // both instances hold a pattern that matches
+        let report = first
+            .send_message("room.7.typing", MockMessage::new(2))
+            .await
+            .unwrap();
+        assert_eq!(report.remote_receivers, 2);
+        // `room.*` on the server also matches this, the broker filters it out for `typing`
+        first
+            .send_message("room.7.typing.extra", MockMessage::new(3))
+            .await
+            .unwrap();
+        let report = first
+            .send_message("user.2.inbox", MockMessage::new(4))
+            .await
+            .unwrap();
+        assert_eq!(report.remote_receivers, 0);
+        wait_until(|| inbox.received().len() == 1 && rooms.received().len() == 2).await;
+        wait_until(|| !typing.received().is_empty()).await;
+
+        assert_eq!(inbox.received(), vec![1]);
+        // the two topics reach the subscriber independently
+        let mut received = rooms.received();
+        received.sort();
+        assert_eq!(received, vec![2, 3]);
+        assert_eq!(typing.received(), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_removing_channels_unsubscribes() {
+        let server = RespServer::new();
+        let addr = server
+            .clone()
+            .spawn(([127, 0, 0, 1], 0).into())
+            .await
+            .unwrap();
+        let broker = connect(addr).await;
+        let subscriber = MockSubscriber::new("a", Behaviour::Ok);
+        broker.add_subscriber("room.>", subscriber.clone()).unwrap();
+        // maps to the same server side pattern as `room.>`
+        broker.create_channel("room.*").unwrap();
+        wait_until(|| server.subscription_count() == 1).await;
+
+        // `room.>` still needs the pattern, so the server keeps it
+        broker.delete_channel("room.*").unwrap();
+        let report = broker
+            .send_message("room.1", MockMessage::new(1))
+            .await
+            .unwrap();
+        assert_eq!(report.remote_receivers, 1);
+        wait_until(|| !subscriber.received().is_empty()).await;
+        assert_eq!(subscriber.received(), vec![1]);
+
+        broker.delete_channel("room.>").unwrap();
+        wait_until(|| server.subscription_count() == 0).await;
+        let report = broker
+            .send_message("room.1", MockMessage::new(2))
+            .await
+            .unwrap();
+        assert_eq!(report.remote_receivers, 0);
+    }
+
+    #[tokio::test]
+    async fn test_last_subscriber_leaving_unsubscribes() {
+        let server = RespServer::new();
+        let addr = server
+            .clone()
+            .spawn(([127, 0, 0, 1], 0).into())
+            .await
+            .unwrap();
+        let broker = connect(addr).await;
+        let first = MockSubscriber::new("a", Behaviour::Ok);
+        let second = MockSubscriber::new("b", Behaviour::Ok);
+        broker
+            .add_subscriber("user.1.inbox", first.clone())
+            .unwrap();
+        broker
+            .add_subscriber("user.1.inbox", second.clone())
+            .unwrap();
+        wait_until(|| server.subscription_count() == 1).await;
+
+        // `second` still listens, so the server keeps the subscription
+        broker.remove_subscriber("user.1.inbox", &first).unwrap();
+        let report = broker
+            .send_message("user.1.inbox", MockMessage::new(1))
+            .await
+            .unwrap();
+        assert_eq!(report.remote_receivers, 1);
+
+        broker.remove_subscriber("user.1.inbox", &second).unwrap();
+        wait_until(|| server.subscription_count() == 0).await;
+        assert!(broker.list_channels().is_empty());
+        let report = broker
+            .send_message("user.1.inbox", MockMessage::new(2))
+            .await
+            .unwrap();
+        assert_eq!(report.remote_receivers, 0);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("room.*", "room.1.typing", true),
+            ("room.*", "room", false),
+            ("*", "", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-c]llo", "hbllo", true),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            (r"a\*", "a*", true),
+            (r"a\*", "ab", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), text.as_bytes()),
+                *expected,
+                "{pattern} ~ {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_subscription_for_pattern() {
+        assert_eq!(
+            RedisSubscription::for_pattern("user.1.inbox"),
+            RedisSubscription::Channel("user.1.inbox".into())
+        );
+        assert_eq!(
+            RedisSubscription::for_pattern("room.*.typ?ng"),
+            RedisSubscription::Pattern(r"room.*.typ\?ng".into())
+        );
+        assert_eq!(
+            RedisSubscription::for_pattern("room.>"),
+            RedisSubscription::Pattern("room.*".into())
+        );
+
+        let RedisSubscription::Pattern(glob) = RedisSubscription::for_pattern("a[1].*") else {
+            unreachable!()
+        };
+        assert!(glob_match(glob.as_bytes(), b"a[1].x"));
+        assert!(!glob_match(glob.as_bytes(), b"a1.x"));
+    }
+}
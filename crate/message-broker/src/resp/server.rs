@@ -0,0 +1,492 @@
+//! A minimal pub/sub only server speaking RESP2, for local development and tests.
+//! It understands `SUBSCRIBE`, `UNSUBSCRIBE`, `PSUBSCRIBE`, `PUNSUBSCRIBE`, `PUBLISH`,
+//! `PING` and `QUIT`, nothing else.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Notify},
+};
+
+use super::{
+    codec::{read_value, Value},
+    glob_match,
+};
+
+/// Frames a client may have waiting before it is disconnected for being too slow.
+const OUTPUT_BUFFER: usize = 1024;
+
+#[derive(Clone)]
+struct Client {
+    frames: mpsc::Sender<Vec<u8>>,
+    /// Notified when `frames` overflowed and the connection has to go.
+    overflowed: Arc<Notify>,
+}
+
+impl Client {
+    fn push(&self, frame: Vec<u8>) -> bool {
+        match self.frames.try_send(frame) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.overflowed.notify_one();
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    channels: HashMap<Vec<u8>, HashMap<u64, Client>>,
+    patterns: HashMap<Vec<u8>, HashMap<u64, Client>>,
+}
+
+impl Registry {
+    fn publish(&self, channel: &[u8], message: &[u8]) -> i64 {
+        let mut receivers = 0;
+
+        if let Some(clients) = self.channels.get(channel) {
+            let frame = Value::Array(vec![
+                Value::bulk("message"),
+                Value::bulk(channel),
+                Value::bulk(message),
+            ])
+            .to_bytes();
+            receivers += clients
+                .values()
+                .filter(|client| client.push(frame.clone()))
+                .count();
+        }
+
+        for (pattern, clients) in &self.patterns {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            let frame = Value::Array(vec![
+                Value::bulk("pmessage"),
+                Value::bulk(pattern.as_slice()),
+                Value::bulk(channel),
+                Value::bulk(message),
+            ])
+            .to_bytes();
+            receivers += clients
+                .values()
+                .filter(|client| client.push(frame.clone()))
+                .count();
+        }
+
+        receivers as i64
+    }
+}
+
+/// Runs until the listener fails. Each connection is served on its own task.
+#[derive(Clone, Default)]
+pub struct RespServer {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl RespServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                let _ = server.handle_connection(stream).await;
+            });
+        }
+    }
+
+    /// Binds `addr` and serves in the background, returning the address actually bound,
+    /// so `127.0.0.1:0` picks a free port.
+    pub async fn spawn(self, addr: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        tokio::spawn(self.serve(listener));
+
+        Ok(local_addr)
+    }
+
+    /// Channel and pattern subscriptions held across all connections.
+    pub fn subscription_count(&self) -> usize {
+        let registry = self.registry.lock();
+        registry
+            .channels
+            .values()
+            .chain(registry.patterns.values())
+            .map(HashMap::len)
+            .sum()
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+
+        let (frames, mut outgoing) = mpsc::channel::<Vec<u8>>(OUTPUT_BUFFER);
+        let client = Client {
+            frames,
+            overflowed: Arc::new(Notify::new()),
+        };
+        let mut connection = Connection {
+            id: {
+                let mut registry = self.registry.lock();
+                registry.next_id += 1;
+                registry.next_id
+            },
+            client: client.clone(),
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+        };
+
+        let mut writer = tokio::spawn(async move {
+            while let Some(frame) = outgoing.recv().await {
+                write.write_all(&frame).await?;
+            }
+            io::Result::Ok(())
+        });
+
+        let result = loop {
+            tokio::select! {
+                value = read_value(&mut reader) => {
+                    let value = match value {
+                        Ok(Some(value)) => value,
+                        Ok(None) => break Ok(()),
+                        Err(err) => break Err(err),
+                    };
+                    match self.handle_command(&mut connection, value).await {
+                        Ok(true) => {}
+                        Ok(false) => break Ok(()),
+                        Err(err) => break Err(err),
+                    }
+                }
+                _ = client.overflowed.notified() => {
+                    break Err(io::Error::other("client too slow"));
+                }
+                _ = &mut writer => break Ok(()),
+            }
+        };
+
+        self.unsubscribe_all(&connection);
+        // lets the writer flush whatever is queued, then stop
+        drop(connection);
+        drop(client);
+        let _ = writer.await;
+
+        result
+    }
+
+    /// Returns `false` once the connection should be closed.
+    async fn handle_command(&self, connection: &mut Connection, value: Value) -> io::Result<bool> {
+        let Value::Array(parts) = value else {
+            connection
+                .reply(Value::Error("ERR expected an array of bulk strings".into()))
+                .await?;
+            return Ok(true);
+        };
+        let Some(args) = parts
+            .iter()
+            .map(|part| part.as_bytes().map(<[u8]>::to_vec))
+            .collect::<Option<Vec<_>>>()
+        else {
+            connection
+                .reply(Value::Error("ERR expected an array of bulk strings".into()))
+                .await?;
+            return Ok(true);
+        };
+        let Some((name, args)) = args.split_first() else {
+            return Ok(true);
+        };
+        let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+
+        if connection.is_subscribed()
+            && !matches!(
+                name.as_str(),
+                "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE" | "PING" | "QUIT"
+            )
+        {
+            let error = format!(
+                "ERR Can't execute '{}' in subscribe mode",
+                name.to_lowercase()
+            );
+            connection.reply(Value::Error(error)).await?;
+            return Ok(true);
+        }
+
+        let arity_ok = match name.as_str() {
+            "SUBSCRIBE" | "PSUBSCRIBE" => !args.is_empty(),
+            "PUBLISH" => args.len() == 2,
+            _ => true,
+        };
+        if !arity_ok {
+            let error = format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_lowercase()
+            );
+            connection.reply(Value::Error(error)).await?;
+            return Ok(true);
+        }
+
+        match (name.as_str(), args) {
+            ("SUBSCRIBE", topics) => {
+                for topic in topics {
+                    self.subscribe(connection, Kind::Channel, topic.clone())
+                        .await?;
+                }
+            }
+            ("PSUBSCRIBE", patterns) => {
+                for pattern in patterns {
+                    self.subscribe(connection, Kind::Pattern, pattern.clone())
+                        .await?;
+                }
+            }
+            ("UNSUBSCRIBE", topics) => self.unsubscribe(connection, Kind::Channel, topics).await?,
+            ("PUNSUBSCRIBE", patterns) => {
+                self.unsubscribe(connection, Kind::Pattern, patterns)
+                    .await?
+            }
+            ("PUBLISH", [channel, message]) => {
+                let receivers = self.registry.lock().publish(channel, message);
+                connection.reply(Value::Integer(receivers)).await?;
+            }
+            ("PING", args) if connection.is_subscribed() => {
+                let message = args.first().cloned().unwrap_or_default();
+                connection
+                    .reply(Value::Array(vec![
+                        Value::bulk("pong"),
+                        Value::Bulk(message),
+                    ]))
+                    .await?;
+            }
+            ("PING", [message]) => connection.reply(Value::Bulk(message.clone())).await?,
+            ("PING", _) => connection.reply(Value::Simple("PONG".into())).await?,
+            ("QUIT", _) => {
+                connection.reply(Value::Simple("OK".into())).await?;
+                return Ok(false);
+            }
+            _ => {
+                let error = format!("ERR unknown command '{}'", name.to_lowercase());
+                connection.reply(Value::Error(error)).await?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn subscribe(
+        &self,
+        connection: &mut Connection,
+        kind: Kind,
+        name: Vec<u8>,
+    ) -> io::Result<()> {
+        {
+            let mut registry = self.registry.lock();
+            let map = match kind {
+                Kind::Channel => &mut registry.channels,
+                Kind::Pattern => &mut registry.patterns,
+            };
+            map.entry(name.clone())
+                .or_default()
+                .insert(connection.id, connection.client.clone());
+        }
+        connection.set_mut(kind).insert(name.clone());
+
+        let count = connection.count();
+        connection
+            .reply(Value::Array(vec![
+                Value::bulk(kind.subscribe_reply()),
+                Value::Bulk(name),
+                Value::Integer(count),
+            ]))
+            .await
+    }
+
+    async fn unsubscribe(
+        &self,
+        connection: &mut Connection,
+        kind: Kind,
+        names: &[Vec<u8>],
+    ) -> io::Result<()> {
+        // without arguments, everything of that kind
+        let names: Vec<Vec<u8>> = if names.is_empty() {
+            connection.set_mut(kind).iter().cloned().collect()
+        } else {
+            names.to_vec()
+        };
+
+        if names.is_empty() {
+            let count = connection.count();
+            return connection
+                .reply(Value::Array(vec![
+                    Value::bulk(kind.unsubscribe_reply()),
+                    Value::Null,
+                    Value::Integer(count),
+                ]))
+                .await;
+        }
+
+        for name in names {
+            self.remove(kind, &name, connection.id);
+            connection.set_mut(kind).remove(&name);
+            let count = connection.count();
+            connection
+                .reply(Value::Array(vec![
+                    Value::bulk(kind.unsubscribe_reply()),
+                    Value::Bulk(name),
+                    Value::Integer(count),
+                ]))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, kind: Kind, name: &[u8], id: u64) {
+        let mut registry = self.registry.lock();
+        let map = match kind {
+            Kind::Channel => &mut registry.channels,
+            Kind::Pattern => &mut registry.patterns,
+        };
+        if let Some(clients) = map.get_mut(name) {
+            clients.remove(&id);
+            if clients.is_empty() {
+                map.remove(name);
+            }
+        }
+    }
+
+    fn unsubscribe_all(&self, connection: &Connection) {
+        for name in &connection.channels {
+            self.remove(Kind::Channel, name, connection.id);
+        }
+        for name in &connection.patterns {
+            self.remove(Kind::Pattern, name, connection.id);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Channel,
+    Pattern,
+}
+
+impl Kind {
+    fn subscribe_reply(self) -> &'static str {
+        match self {
+            Self::Channel => "subscribe",
+            Self::Pattern => "psubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(self) -> &'static str {
+        match self {
+            Self::Channel => "unsubscribe",
+            Self::Pattern => "punsubscribe",
+        }
+    }
+}
+
+struct Connection {
+    id: u64,
+    client: Client,
+    channels: HashSet<Vec<u8>>,
+    patterns: HashSet<Vec<u8>>,
+}
+
+impl Connection {
+    fn is_subscribed(&self) -> bool {
+        self.count() > 0
+    }
+
+    fn count(&self) -> i64 {
+        (self.channels.len() + self.patterns.len()) as i64
+    }
+
+    fn set_mut(&mut self, kind: Kind) -> &mut HashSet<Vec<u8>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+        }
+    }
+
+    /// Replies go through the same queue as pushed messages, so they stay in order.
+    async fn reply(&self, value: Value) -> io::Result<()> {
+        self.client
+            .frames
+            .send(value.to_bytes())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection closed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn roundtrip(
+        reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+        writer: &mut tokio::net::tcp::OwnedWriteHalf,
+        parts: &[&str],
+    ) -> Value {
+        let command = Value::command(parts.iter().map(|part| part.as_bytes()));
+        writer.write_all(&command.to_bytes()).await.unwrap();
+        read_value(reader).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let addr = RespServer::new()
+            .spawn(([127, 0, 0, 1], 0).into())
+            .await
+            .unwrap();
+        let (read, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(read);
+
+        assert_eq!(
+            roundtrip(&mut reader, &mut writer, &["PING"]).await,
+            Value::Simple("PONG".into())
+        );
+        assert!(matches!(
+            roundtrip(&mut reader, &mut writer, &["GET", "key"]).await,
+            Value::Error(_)
+        ));
+        assert_eq!(
+            roundtrip(&mut reader, &mut writer, &["subscribe", "a"]).await,
+            Value::Array(vec![
+                Value::bulk("subscribe"),
+                Value::bulk("a"),
+                Value::Integer(1)
+            ])
+        );
+        // only pub/sub commands are allowed once subscribed
+        assert!(matches!(
+            roundtrip(&mut reader, &mut writer, &["PUBLISH", "a", "x"]).await,
+            Value::Error(_)
+        ));
+        assert_eq!(
+            roundtrip(&mut reader, &mut writer, &["UNSUBSCRIBE"]).await,
+            Value::Array(vec![
+                Value::bulk("unsubscribe"),
+                Value::bulk("a"),
+                Value::Integer(0)
+            ])
+        );
+        assert_eq!(
+            roundtrip(&mut reader, &mut writer, &["PUBLISH", "a", "x"]).await,
+            Value::Integer(0)
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::DeliveryOutcome;
+
+/// Point in time view of a broker, see [`InMemoryBroker::stats`](crate::InMemoryBroker::stats).
+/// Counters are totals since the broker was created.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BrokerStats {
+    pub channels: usize,
+    /// Subscriber and channel pairs; a subscriber on two channels counts twice.
+    pub subscriptions: usize,
+    pub cached_messages: usize,
+    /// Successful `send_message` calls.
+    pub published: u64,
+    /// Deliveries a subscriber accepted, redeliveries included.
+    pub delivered: u64,
+    pub failed: u64,
+    pub timed_out: u64,
+    pub evicted: u64,
+    pub dead_lettered: u64,
+    /// Over every delivery attempt, whatever its outcome.
+    pub mean_delivery_latency: Duration,
+    pub max_delivery_latency: Duration,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    published: AtomicU64,
+    delivered: AtomicU64,
+    failed: AtomicU64,
+    timed_out: AtomicU64,
+    evicted: AtomicU64,
+    dead_lettered: AtomicU64,
+    attempts: AtomicU64,
+    latency_total_nanos: AtomicU64,
+    latency_max_nanos: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn record_publish(&self) {
+        self.published.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_delivery(&self, outcome: &DeliveryOutcome, elapsed: Duration) {
+        let counter = match outcome {
+            DeliveryOutcome::Delivered => &self.delivered,
+            DeliveryOutcome::Failed(_) => &self.failed,
+            DeliveryOutcome::TimedOut => &self.timed_out,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        self.latency_total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.latency_max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_evictions(&self, count: usize) {
+        self.evicted.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dead_letter(&self) {
+        self.dead_lettered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fills in the counters; channel figures are left to the caller.
+    pub(crate) fn snapshot(&self) -> BrokerStats {
+        let attempts = self.attempts.load(Ordering::Relaxed);
+        let total = self.latency_total_nanos.load(Ordering::Relaxed);
+
+        BrokerStats {
+            published: self.published.load(Ordering::Relaxed),
+            delivered: self.delivered.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+            dead_lettered: self.dead_lettered.load(Ordering::Relaxed),
+            mean_delivery_latency: Duration::from_nanos(total.checked_div(attempts).unwrap_or(0)),
+            max_delivery_latency: Duration::from_nanos(
+                self.latency_max_nanos.load(Ordering::Relaxed),
+            ),
+            ..BrokerStats::default()
+        }
+    }
+}
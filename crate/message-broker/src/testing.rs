@@ -0,0 +1,230 @@
+//! Building blocks for deterministic broker tests.
+//!
+//! Run tests with `#[tokio::test(start_paused = true)]` so timeouts and backoff
+//! elapse instantly, and hand a [`VirtualClock`] to the broker via
+//! [`InMemoryBroker::with_clock`](crate::InMemoryBroker::with_clock) so cache retention
+//! follows the same paused time. Enable the `test-support` feature to use it from other crates.
+
+use std::{hash::Hash, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{Clock, DurableSubscriber, Error, Message, Subscriber};
+
+/// Wall clock that only moves with tokio's clock and [`VirtualClock::advance`].
+///
+/// Under a paused runtime `tokio::time::advance` and auto-advancing sleeps move it too,
+/// so message times, retention and delivery timeouts all agree.
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    start: chrono::DateTime<chrono::Utc>,
+    origin: tokio::time::Instant,
+    offset: Arc<Mutex<chrono::Duration>>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualClock {
+    /// Starts at a fixed instant, so test output does not depend on the day it runs.
+    pub fn new() -> Self {
+        let start = chrono::DateTime::from_timestamp(1_700_000_000, 0).expect("valid timestamp");
+        Self::starting_at(start)
+    }
+
+    pub fn starting_at(start: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            start,
+            origin: tokio::time::Instant::now(),
+            offset: Arc::default(),
+        }
+    }
+
+    /// Moves this clock, and every clone of it, forward without touching tokio's clock.
+    pub fn advance(&self, duration: Duration) {
+        let duration = chrono::Duration::from_std(duration).expect("duration out of range");
+        *self.offset.lock() += duration;
+    }
+
+    /// A message stamped with the current virtual time.
+    pub fn message(&self, id: u32) -> MockMessage {
+        MockMessage {
+            id,
+            time: self.now(),
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        let elapsed = chrono::Duration::from_std(self.origin.elapsed()).unwrap_or_default();
+        self.start + elapsed + *self.offset.lock()
+    }
+}
+
+/// Message whose id is also its payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MockMessage {
+    pub id: u32,
+    pub time: chrono::DateTime<chrono::Utc>,
+}
+
+impl MockMessage {
+    /// Stamped with the real time, see [`VirtualClock::message`] for virtual time.
+    pub fn new(id: u32) -> Self {
+        Self {
+            id,
+            time: chrono::Utc::now(),
+        }
+    }
+}
+
+impl Message<u32> for MockMessage {
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    fn time(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time
+    }
+
+    fn payload(&self) -> &u32 {
+        &self.id
+    }
+}
+
+/// How a [`MockSubscriber`] answers deliveries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Behaviour {
+    Ok,
+    Fail,
+    /// Sleeps on tokio's clock before accepting.
+    Slow(Duration),
+    /// Fails this many times, then accepts.
+    FailFirst(u32),
+}
+
+/// Subscriber that records what it accepted and when it was tried.
+/// Clones share their records; equality and hashing go by name.
+#[derive(Debug, Clone)]
+pub struct MockSubscriber {
+    name: &'static str,
+    behaviour: Behaviour,
+    received: Arc<Mutex<Vec<u32>>>,
+    attempted_at: Arc<Mutex<Vec<tokio::time::Instant>>>,
+}
+
+impl MockSubscriber {
+    pub fn new(name: &'static str, behaviour: Behaviour) -> Self {
+        Self {
+            name,
+            behaviour,
+            received: Arc::default(),
+            attempted_at: Arc::default(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Ids of the accepted messages, in order.
+    pub fn received(&self) -> Vec<u32> {
+        self.received.lock().clone()
+    }
+
+    /// Every call to `on_message`, accepted or not.
+    pub fn attempts(&self) -> u32 {
+        self.attempted_at.lock().len() as u32
+    }
+
+    /// When each attempt started, on tokio's clock.
+    pub fn attempted_at(&self) -> Vec<tokio::time::Instant> {
+        self.attempted_at.lock().clone()
+    }
+}
+
+impl PartialEq for MockSubscriber {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for MockSubscriber {}
+
+impl Hash for MockSubscriber {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl DurableSubscriber for MockSubscriber {
+    fn durable_name(&self) -> String {
+        self.name.to_string()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscriber<u32, MockMessage> for MockSubscriber {
+    async fn on_message(&self, message: &MockMessage) -> Result<(), Error> {
+        let attempt = {
+            let mut attempted_at = self.attempted_at.lock();
+            attempted_at.push(tokio::time::Instant::now());
+            attempted_at.len() as u32
+        };
+        match self.behaviour {
+            Behaviour::Ok => {}
+            Behaviour::Fail => return Err(Error::SubscriberGoneBad(self.name.to_string())),
+            Behaviour::Slow(delay) => tokio::time::sleep(delay).await,
+            Behaviour::FailFirst(failures) if attempt <= failures => {
+                return Err(Error::SubscriberGoneBad(self.name.to_string()))
+            }
+            Behaviour::FailFirst(_) => {}
+        }
+        self.received.lock().push(message.id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_virtual_clock_follows_paused_tokio_time() {
+        let clock = VirtualClock::new();
+        let start = clock.now();
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(
+            clock.now() - start,
+            chrono::Duration::try_seconds(10).unwrap()
+        );
+
+        clock.clone().advance(Duration::from_secs(5));
+        assert_eq!(
+            clock.now() - start,
+            chrono::Duration::try_seconds(15).unwrap()
+        );
+        assert_eq!(clock.message(1).time, clock.now());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mock_subscriber_follows_its_script() {
+        let flaky = MockSubscriber::new("flaky", Behaviour::FailFirst(1));
+        assert!(flaky.on_message(&MockMessage::new(1)).await.is_err());
+        assert!(flaky.on_message(&MockMessage::new(2)).await.is_ok());
+        assert_eq!(flaky.received(), vec![2]);
+        assert_eq!(flaky.attempts(), 2);
+
+        let slow = MockSubscriber::new("slow", Behaviour::Slow(Duration::from_secs(3)));
+        let start = tokio::time::Instant::now();
+        slow.on_message(&MockMessage::new(1)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+        assert_eq!(slow.attempted_at(), vec![start]);
+    }
+}
@@ -0,0 +1,292 @@
+//! Hierarchical topics in the NATS style.
+//!
+//! A topic is a list of `.` separated tokens such as `user.<uuid>.inbox`.
+//! A subscription pattern may use `*` to match exactly one token and,
+//! as its last token, `>` to match one or more remaining tokens:
+//! `room.*.typing` matches `room.42.typing`, `room.>` matches `room.42` and `room.42.typing`.
+
+use std::{borrow::Cow, collections::HashMap, fmt::Display, hash::Hash};
+
+use crate::Error;
+
+const SEPARATOR: char = '.';
+const SINGLE_WILDCARD: &str = "*";
+const TAIL_WILDCARD: &str = ">";
+
+/// A key the broker API accepts wherever it takes a topic.
+///
+/// Strings work as they are. Applications can instead route through their own type,
+/// whose [`Display`] output is the topic, and let the compiler catch typos:
+///
+/// ```
+/// use std::fmt;
+///
+/// #[derive(Hash, PartialEq, Eq)]
+/// enum AppTopic {
+///     Room(i32),
+///     Presence,
+/// }
+///
+/// impl fmt::Display for AppTopic {
+///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+///         match self {
+///             Self::Room(id) => write!(f, "room.{id}"),
+///             Self::Presence => write!(f, "presence"),
+///         }
+///     }
+/// }
+///
+/// impl message_broker::Topic for AppTopic {}
+///
+/// assert_eq!(message_broker::Topic::topic(&AppTopic::Room(7)), "room.7");
+/// ```
+pub trait Topic: Hash + Eq + Display + Send + Sync {
+    fn topic(&self) -> Cow<'_, str> {
+        Cow::Owned(self.to_string())
+    }
+}
+
+impl<K> Topic for K
+where
+    K: AsRef<str> + Hash + Eq + Display + Send + Sync + ?Sized,
+{
+    fn topic(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.as_ref())
+    }
+}
+
+/// Checks that `pattern` is usable as a channel name; wildcards are allowed.
+pub(crate) fn validate_pattern(pattern: &str) -> Result<(), Error> {
+    let mut tokens = pattern.split(SEPARATOR).peekable();
+    while let Some(token) = tokens.next() {
+        let misplaced_tail = token == TAIL_WILDCARD && tokens.peek().is_some();
+        if token.is_empty() || misplaced_tail {
+            return Err(Error::InvalidTopic(pattern.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `topic` can be published to: a valid pattern without wildcards.
+pub(crate) fn validate_topic(topic: &str) -> Result<(), Error> {
+    validate_pattern(topic)?;
+    if topic
+        .split(SEPARATOR)
+        .any(|token| token == SINGLE_WILDCARD || token == TAIL_WILDCARD)
+    {
+        return Err(Error::InvalidTopic(topic.to_string()));
+    }
+    Ok(())
+}
+
+/// Maps subscription patterns to values, one token per level,
+/// so finding every pattern that matches a topic only walks the topic's own tokens.
+#[derive(Debug)]
+pub(crate) struct TopicTrie<C> {
+    root: Node<C>,
+}
+
+#[derive(Debug)]
+struct Node<C> {
+    children: HashMap<String, Node<C>>,
+    value: Option<C>,
+}
+
+impl<C> Default for Node<C> {
+    fn default() -> Self {
+        Self {
+            children: HashMap::new(),
+            value: None,
+        }
+    }
+}
+
+impl<C> Default for TopicTrie<C> {
+    fn default() -> Self {
+        Self {
+            root: Node::default(),
+        }
+    }
+}
+
+impl<C> TopicTrie<C> {
+    pub(crate) fn get(&self, pattern: &str) -> Option<&C> {
+        let mut node = &self.root;
+        for token in pattern.split(SEPARATOR) {
+            node = node.children.get(token)?;
+        }
+        node.value.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, pattern: &str) -> Option<&mut C> {
+        let mut node = &mut self.root;
+        for token in pattern.split(SEPARATOR) {
+            node = node.children.get_mut(token)?;
+        }
+        node.value.as_mut()
+    }
+
+    /// The value slot for `pattern`, creating the path to it if needed.
+    pub(crate) fn slot(&mut self, pattern: &str) -> &mut Option<C> {
+        let mut node = &mut self.root;
+        for token in pattern.split(SEPARATOR) {
+            node = node.children.entry(token.to_string()).or_default();
+        }
+        &mut node.value
+    }
+
+    pub(crate) fn remove(&mut self, pattern: &str) -> Option<C> {
+        let tokens: Vec<&str> = pattern.split(SEPARATOR).collect();
+        Self::remove_from(&mut self.root, &tokens)
+    }
+
+    fn remove_from(node: &mut Node<C>, tokens: &[&str]) -> Option<C> {
+        let Some((head, rest)) = tokens.split_first() else {
+            return node.value.take();
+        };
+
+        let child = node.children.get_mut(*head)?;
+        let removed = Self::remove_from(child, rest);
+        // prune branches that no longer lead to a value
+        if child.value.is_none() && child.children.is_empty() {
+            node.children.remove(*head);
+        }
+        removed
+    }
+
+    /// Calls `f` with every stored pattern that matches the concrete `topic`.
+    pub(crate) fn for_each_match_mut(&mut self, topic: &str, mut f: impl FnMut(&str, &mut C)) {
+        let tokens: Vec<&str> = topic.split(SEPARATOR).collect();
+        let mut path = Vec::with_capacity(tokens.len());
+        Self::match_from(&mut self.root, &tokens, &mut path, &mut f);
+    }
+
+    fn match_from<'t>(
+        node: &mut Node<C>,
+        tokens: &[&'t str],
+        path: &mut Vec<&'t str>,
+        f: &mut impl FnMut(&str, &mut C),
+    ) {
+        let Some((head, rest)) = tokens.split_first() else {
+            if let Some(value) = node.value.as_mut() {
+                f(&path.join("."), value);
+            }
+            return;
+        };
+
+        if let Some(child) = node.children.get_mut(*head) {
+            path.push(head);
+            Self::match_from(child, rest, path, f);
+            path.pop();
+        }
+        if let Some(child) = node.children.get_mut(SINGLE_WILDCARD) {
+            path.push(SINGLE_WILDCARD);
+            Self::match_from(child, rest, path, f);
+            path.pop();
+        }
+        if let Some(value) = node
+            .children
+            .get_mut(TAIL_WILDCARD)
+            .and_then(|child| child.value.as_mut())
+        {
+            path.push(TAIL_WILDCARD);
+            f(&path.join("."), value);
+            path.pop();
+        }
+    }
+
+    /// Every stored pattern with its value.
+    pub(crate) fn iter(&self) -> Vec<(String, &C)> {
+        let mut entries = Vec::new();
+        let mut path = Vec::new();
+        Self::collect_from(&self.root, &mut path, &mut entries);
+        entries
+    }
+
+    fn collect_from<'a>(
+        node: &'a Node<C>,
+        path: &mut Vec<&'a str>,
+        entries: &mut Vec<(String, &'a C)>,
+    ) {
+        if let Some(value) = node.value.as_ref() {
+            entries.push((path.join("."), value));
+        }
+        for (token, child) in &node.children {
+            path.push(token);
+            Self::collect_from(child, path, entries);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(trie: &mut TopicTrie<()>, topic: &str) -> Vec<String> {
+        let mut patterns = Vec::new();
+        trie.for_each_match_mut(topic, |pattern, _| patterns.push(pattern.to_string()));
+        patterns.sort();
+        patterns
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate_pattern("user.1.inbox").is_ok());
+        assert!(validate_pattern("room.*.typing").is_ok());
+        assert!(validate_pattern("room.>").is_ok());
+        assert!(validate_pattern("room.>.typing").is_err());
+        assert!(validate_pattern("room..typing").is_err());
+        assert!(validate_pattern("").is_err());
+
+        assert!(validate_topic("room.1.typing").is_ok());
+        assert!(validate_topic("room.*.typing").is_err());
+        assert!(validate_topic("room.>").is_err());
+    }
+
+    #[test]
+    fn test_wildcard_matching() {
+        let mut trie = TopicTrie::default();
+        for pattern in [
+            "room.1.typing",
+            "room.*.typing",
+            "room.>",
+            "user.*.inbox",
+            ">",
+        ] {
+            *trie.slot(pattern) = Some(());
+        }
+
+        assert_eq!(
+            matches(&mut trie, "room.1.typing"),
+            vec![">", "room.*.typing", "room.1.typing", "room.>"]
+        );
+        assert_eq!(
+            matches(&mut trie, "room.2.typing"),
+            vec![">", "room.*.typing", "room.>"]
+        );
+        assert_eq!(matches(&mut trie, "room.2"), vec![">", "room.>"]);
+        // `>` needs at least one token after the prefix
+        assert_eq!(matches(&mut trie, "room"), vec![">"]);
+        assert_eq!(
+            matches(&mut trie, "user.1.inbox"),
+            vec![">", "user.*.inbox"]
+        );
+        assert_eq!(matches(&mut trie, "user.1.outbox"), vec![">"]);
+    }
+
+    #[test]
+    fn test_remove_prunes_empty_branches() {
+        let mut trie = TopicTrie::default();
+        *trie.slot("a.b.c") = Some(1);
+        *trie.slot("a") = Some(2);
+
+        assert_eq!(trie.remove("a.b.c"), Some(1));
+        assert_eq!(trie.remove("a.b.c"), None);
+        assert!(trie.root.children["a"].children.is_empty());
+        assert_eq!(trie.get("a"), Some(&2));
+
+        let entries: Vec<_> = trie.iter().into_iter().map(|(p, v)| (p, *v)).collect();
+        assert_eq!(entries, vec![("a".to_string(), 2)]);
+    }
+}
@@ -4,7 +4,8 @@ use crossterm::event::Event;
 use ratatui::{backend::Backend, widgets::Widget, Frame, Terminal};
 
 use crate::{
-    control::{self, AppMode, State},
+    connection,
+    control::{self, typing::TypingIndicators, AppMode, State},
     term::next_event,
     ui::Ui,
 };
@@ -16,6 +17,7 @@ pub struct App {
     ui: Ui,
     state: State,
     functional_keys: HashMap<key_actions::KeyIdentifier, key_actions::KeyAction>,
+    typing: TypingIndicators,
 }
 
 impl Default for App {
@@ -31,6 +33,7 @@ impl App {
             ui: Ui,
             state: State::default(),
             functional_keys: key_events::functional_key_actions(),
+            typing: TypingIndicators::default(),
         }
     }
 
@@ -41,6 +44,7 @@ impl App {
         ctrlc::set_handler(move || {
             sender_clone.send(RuimEvent::CtrlC).unwrap();
         })?;
+        connection::spawn(sender.clone());
 
         std::thread::spawn(move || loop {
             if let Ok(Some(event)) = next_event(Duration::from_millis(50)) {
@@ -65,11 +69,13 @@ impl App {
     }
 
     fn handle_events(&mut self, receiver: &flume::Receiver<RuimEvent>) -> anyhow::Result<()> {
-        if let Ok(event) = receiver.recv() {
+        // wake up now and then so expired typing indicators disappear
+        if let Ok(event) = receiver.recv_timeout(Duration::from_millis(500)) {
             tracing::info!(?event, "event received");
             match event {
                 RuimEvent::CtrlC => self.state.quit(),
                 RuimEvent::Key(key) => self.handle_key_press(key),
+                RuimEvent::Server(msg) => self.typing.apply(&msg),
             }
         }
         Ok(())
@@ -113,7 +119,8 @@ impl Widget for &mut App {
                 // render settings page
             }
             control::Page::Chat => {
-                // render chat page
+                self.state.new_page(control::Page::Chat);
+                self.ui.render_chat(&mut self.typing, area, buf);
             }
         }
     }
@@ -123,4 +130,5 @@ impl Widget for &mut App {
 pub(crate) enum RuimEvent {
     CtrlC,
     Key(crossterm::event::KeyEvent),
+    Server(api_models::chat::ServerMessage),
 }
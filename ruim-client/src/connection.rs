@@ -0,0 +1,44 @@
+use api_models::chat::ServerMessage;
+use tungstenite::{client::IntoClientRequest, http::HeaderValue, Message};
+
+use crate::app::RuimEvent;
+
+/// Where the chat websocket lives, e.g. `ws://127.0.0.1:3000/api/chat`.
+const SERVER_URL_ENV: &str = "RUIM_CLIENT_SERVER_URL";
+/// Access token of the signed in user.
+const TOKEN_ENV: &str = "RUIM_CLIENT_TOKEN";
+
+/// Reads the chat websocket on a thread of its own and forwards every server message
+/// as a [`RuimEvent::Server`]. Without a server url and token the client stays offline.
+pub(crate) fn spawn(sender: flume::Sender<RuimEvent>) {
+    let (Ok(url), Ok(token)) = (std::env::var(SERVER_URL_ENV), std::env::var(TOKEN_ENV)) else {
+        tracing::info!("no server configured, running offline");
+        return;
+    };
+
+    std::thread::spawn(move || {
+        if let Err(err) = read(&url, &token, &sender) {
+            tracing::error!(?err, %url, "server connection lost");
+        }
+    });
+}
+
+fn read(url: &str, token: &str, sender: &flume::Sender<RuimEvent>) -> anyhow::Result<()> {
+    let mut request = url.into_client_request()?;
+    request.headers_mut().insert(
+        "Authorization",
+        HeaderValue::from_str(&format!("Bearer {token}"))?,
+    );
+    let (mut socket, _) = tungstenite::connect(request)?;
+    tracing::info!(%url, "connected to server");
+
+    loop {
+        let Message::Text(text) = socket.read()? else {
+            continue;
+        };
+        match serde_json::from_str::<ServerMessage>(&text) {
+            Ok(msg) => sender.send(RuimEvent::Server(msg))?,
+            Err(err) => tracing::warn!(?err, "unexpected message from server"),
+        }
+    }
+}
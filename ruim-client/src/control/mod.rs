@@ -4,6 +4,7 @@ use ratatui::widgets::StatefulWidget;
 
 use crate::ui::{FocusableStatefulWidget, IdentifiableStatefulWidget};
 pub mod state_type;
+pub mod typing;
 pub use state_type::*;
 
 /// Each focusable stateful component (FSC) is identified by a unique ID.
@@ -0,0 +1,63 @@
+use std::{collections::HashMap, time::Instant};
+
+use api_models::chat::{ServerMessage, TypingState, TYPING_TIMEOUT};
+
+/// Who is typing to us right now, by sender id.
+#[derive(Debug, Default)]
+pub struct TypingIndicators {
+    typing: HashMap<String, Typing>,
+}
+
+#[derive(Debug)]
+struct Typing {
+    username: String,
+    expires_at: Instant,
+}
+
+impl TypingIndicators {
+    pub(crate) fn apply(&mut self, msg: &ServerMessage) {
+        match msg {
+            ServerMessage::Typing {
+                sender_id,
+                username,
+                state: TypingState::Started,
+            } => {
+                let typing = Typing {
+                    username: username.clone(),
+                    expires_at: Instant::now() + TYPING_TIMEOUT,
+                };
+                self.typing.insert(sender_id.clone(), typing);
+            }
+            ServerMessage::Typing {
+                sender_id,
+                state: TypingState::Stopped,
+                ..
+            } => {
+                self.typing.remove(sender_id);
+            }
+            // the message the sender was typing has arrived
+            ServerMessage::Regular(body) => {
+                self.typing.remove(&body.sender_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// E.g. "alice is typing…", `None` when nobody is.
+    pub(crate) fn text(&mut self) -> Option<String> {
+        let now = Instant::now();
+        self.typing.retain(|_, typing| typing.expires_at > now);
+
+        let mut usernames: Vec<&str> = self
+            .typing
+            .values()
+            .map(|typing| typing.username.as_str())
+            .collect();
+        usernames.sort_unstable();
+        match usernames.as_slice() {
+            [] => None,
+            [username] => Some(format!("{username} is typing…")),
+            usernames => Some(format!("{} are typing…", usernames.join(", "))),
+        }
+    }
+}
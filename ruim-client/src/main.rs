@@ -3,6 +3,7 @@ use dotenv::dotenv;
 use logging::initialize_logging;
 
 pub mod app;
+mod connection;
 pub mod control;
 pub mod logging;
 pub mod term;
@@ -55,6 +55,37 @@ impl Ui {
             .render(chunks[1], buf);
     }
 
+    pub(crate) fn render_chat(
+        &self,
+        typing: &mut crate::control::typing::TypingIndicators,
+        area: Rect,
+        buf: &mut ratatui::prelude::Buffer,
+    ) {
+        let chat_box = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::White))
+            .title("Chat");
+        let inner = chat_box.inner(area);
+        chat_box.render(area, buf);
+
+        let layout = Layout::default()
+            .direction(ratatui::layout::Direction::Vertical)
+            .constraints(
+                [
+                    ratatui::prelude::Constraint::Fill(1),
+                    ratatui::prelude::Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(inner);
+
+        if let Some(text) = typing.text() {
+            Paragraph::new(text)
+                .style(Style::default().fg(Color::DarkGray))
+                .render(layout[1], buf);
+        }
+    }
+
     pub(crate) fn render_outer_frame<'a>(
         &'a self,
         area: Rect,
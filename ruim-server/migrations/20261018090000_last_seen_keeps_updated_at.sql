@@ -0,0 +1,6 @@
+-- last_seen is written on every connect and disconnect, that is not a profile change
+CREATE OR REPLACE TRIGGER update_user_modtime
+    BEFORE UPDATE OF username, email, hashed_password, accept_public_chat, show_in_public_chat
+    ON users
+    FOR EACH ROW
+    EXECUTE FUNCTION update_modified_column();
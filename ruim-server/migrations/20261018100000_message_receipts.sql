@@ -0,0 +1,7 @@
+-- sent is the row itself, delivered once the receiver connects, read once they say so
+ALTER TABLE messages
+    ADD COLUMN delivered_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN read_at TIMESTAMP WITH TIME ZONE;
+
+-- unread counts and receipts only look at what the receiver has not read yet
+CREATE INDEX messages_unread_idx ON messages (receiver_id, sender_id) WHERE read_at IS NULL;
@@ -0,0 +1,3 @@
+-- history pages walk one direction of a conversation newest first, keyed by (created_at, message_id)
+CREATE INDEX messages_conversation_idx
+    ON messages (sender_id, receiver_id, created_at DESC, message_id DESC);
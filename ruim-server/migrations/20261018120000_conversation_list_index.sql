@@ -0,0 +1,3 @@
+-- the conversation list looks up both directions of every conversation of one user
+CREATE INDEX messages_received_idx
+    ON messages (receiver_id, sender_id, created_at DESC, message_id DESC);
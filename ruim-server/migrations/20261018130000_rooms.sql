@@ -0,0 +1,29 @@
+CREATE TABLE rooms (
+    room_id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    owner_id UUID REFERENCES users(user_id) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE room_members (
+    room_id INTEGER REFERENCES rooms(room_id) ON DELETE CASCADE NOT NULL,
+    user_id UUID REFERENCES users(user_id) ON DELETE CASCADE NOT NULL,
+    role smallint NOT NULL,
+    joined_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (room_id, user_id)
+);
+
+-- the rooms of one user
+CREATE INDEX room_members_user_idx ON room_members (user_id);
+
+CREATE TABLE room_messages (
+    message_id SERIAL PRIMARY KEY,
+    room_id INTEGER REFERENCES rooms(room_id) ON DELETE CASCADE NOT NULL,
+    sender_id UUID REFERENCES users(user_id) NOT NULL,
+    content TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+-- history pages, keyed by (created_at, message_id) like direct messages
+CREATE INDEX room_messages_history_idx
+    ON room_messages (room_id, created_at DESC, message_id DESC);
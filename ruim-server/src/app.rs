@@ -1,8 +1,15 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
-use axum::{middleware, Router};
+use axum::{extract::ws::close_code, middleware, Router};
 
-use crate::{context::RuimContext, handler, service};
+use crate::{
+    context::RuimContext,
+    core::session_manager::{CloseMessage, SessionManager},
+    handler, service,
+};
+
+/// How long sessions get to finish their work once shutdown started.
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
 
 pub fn create_app(state: RuimContext) -> Router {
     Router::new()
@@ -12,6 +19,10 @@ pub fn create_app(state: RuimContext) -> Router {
                 middleware::from_fn_with_state(state.clone(), service::auth::guard),
             ),
         )
+        .nest("/api/chat/conversations", handler::conversation::router())
+        .nest("/api/chat/presence", handler::presence::router())
+        .nest("/api/chat/rooms", handler::room::router())
+        .nest("/api/chat/sessions", handler::session::router())
         .nest("/api/user", handler::user::router(state.clone()))
         .layer(tower_http::trace::TraceLayer::new_for_http())
         .with_state(state)
@@ -24,13 +35,55 @@ pub async fn start_ruim_server() -> anyhow::Result<()> {
         .init();
 
     let state = RuimContext::new().await?;
+    let session_manager = state.session_manager.clone();
     let app = create_app(state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 8888));
     tracing::info!("Listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown(session_manager))
+        .await?;
 
+    tracing::info!("Server stopped");
     Ok(())
 }
+
+/// Resolves once the process was asked to stop and the sessions were drained.
+/// Websockets are detached from their HTTP connection, so they have to be closed
+/// before `axum::serve` stops waiting for the remaining requests.
+async fn shutdown(session_manager: SessionManager) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c()
+            .await
+            .inspect_err(|err| tracing::error!(?err, "failed to listen for Ctrl-C"));
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!(?err, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    tracing::info!("Shutting down");
+    session_manager
+        .shutdown(
+            CloseMessage::new(close_code::AWAY, "Server is shutting down"),
+            SHUTDOWN_DEADLINE,
+        )
+        .await;
+}
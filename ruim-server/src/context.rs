@@ -1,21 +1,54 @@
-use crate::{db, jwt};
+use crate::{
+    core::{
+        broker::{self, RuimBroker},
+        presence::{self, Presence},
+        receipt,
+        session_manager::{HeartbeatConfig, SessionManager},
+    },
+    db, jwt,
+};
 
 #[derive(Clone)]
 pub struct RuimContext {
     pub db: db::Database,
     pub jwt: jwt::Jwt,
-    pub session_manager: crate::core::session_manager::SessionManager,
+    pub session_manager: SessionManager,
+    pub broker: RuimBroker,
+    pub presence: Presence,
 }
 
 impl RuimContext {
     pub async fn new() -> anyhow::Result<Self> {
         let db = db::Database::new().await?;
         let jwt = jwt::Jwt::new_from_env()?;
+        let broker = broker::new_broker().await?;
+
+        let (session_events, session_event_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (deliveries, delivery_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let session_manager = SessionManager::new()
+            .with_heartbeat(HeartbeatConfig::from_env()?)
+            .with_events(session_events)
+            .with_deliveries(deliveries);
+        let presence = Presence::new();
+        presence::spawn(
+            presence.clone(),
+            session_event_receiver,
+            db.clone(),
+            broker.clone(),
+        );
+        receipt::spawn(
+            delivery_receiver,
+            db.clone(),
+            broker.clone(),
+            session_manager.clone(),
+        );
 
         Ok(Self {
             db,
             jwt,
-            session_manager: crate::core::session_manager::SessionManager::new(),
+            session_manager,
+            broker,
+            presence,
         })
     }
 }
@@ -0,0 +1,325 @@
+use api_models::chat::{DeliveryStatus, ServerMessage};
+use axum::extract::FromRef;
+use message_broker::{
+    BrokerReceive, BrokerSend, DeliveryReport, InMemoryBroker, Mailbox, MailboxConfig,
+    OverflowPolicy, RespBroker, RespConfig, SubscribeOptions, Topic,
+};
+use uuid::Uuid;
+
+use crate::context::RuimContext;
+
+use super::session_manager::{SafeWebsocket, SessionManager, WebsocketControlMessage};
+
+/// Every connected websocket subscribes to its user's inbox topic through a [`WebsocketMailbox`],
+/// and everything addressed to a user is published there.
+/// Build it with [`new_broker`].
+#[derive(Clone)]
+pub enum RuimBroker {
+    /// Single server instance.
+    InMemory(InMemoryBroker<ServerMessage, WebsocketMailbox, BrokerMessage>),
+    /// Shared with every other instance connected to the same Redis compatible server.
+    Resp(RespBroker<ServerMessage, WebsocketMailbox, BrokerMessage>),
+}
+
+/// Bounded queue in front of a websocket, so a slow client cannot stall publishers.
+pub type WebsocketMailbox = Mailbox<ServerMessage, SafeWebsocket, BrokerMessage>;
+
+/// A client this far behind is disconnected; it gets the rest from the history once it reconnects.
+const MAILBOX_CONFIG: MailboxConfig = MailboxConfig {
+    capacity: 128,
+    overflow: OverflowPolicy::Disconnect,
+};
+
+/// Connects to the server in `BROKER_URL` (`redis://host:port`) when it is set and not blank,
+/// otherwise keeps everything in this process.
+pub async fn new_broker() -> anyhow::Result<RuimBroker> {
+    let Some(url) = std::env::var("BROKER_URL")
+        .ok()
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+    else {
+        return Ok(RuimBroker::InMemory(
+            InMemoryBroker::new().with_auto_create_channels(true),
+        ));
+    };
+
+    let config = RespConfig {
+        auto_create_channels: true,
+        ..RespConfig::default()
+    };
+    let broker = RespBroker::connect(&url, config).await?;
+    tracing::info!(%url, "connected to message broker");
+
+    Ok(RuimBroker::Resp(broker))
+}
+
+macro_rules! dispatch {
+    ($broker:expr, $inner:ident => $call:expr) => {
+        match $broker {
+            RuimBroker::InMemory($inner) => $call,
+            RuimBroker::Resp($inner) => $call,
+        }
+    };
+}
+
+impl BrokerReceive<ServerMessage, WebsocketMailbox, BrokerMessage> for RuimBroker {
+    fn create_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), message_broker::Error> {
+        dispatch!(self, broker => broker.create_channel(topic))
+    }
+
+    fn delete_channel<K: Topic + ?Sized>(&self, topic: &K) -> Result<(), message_broker::Error> {
+        dispatch!(self, broker => broker.delete_channel(topic))
+    }
+
+    fn add_subscriber<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: WebsocketMailbox,
+    ) -> Result<(), message_broker::Error> {
+        dispatch!(self, broker => broker.add_subscriber(topic, subscriber))
+    }
+
+    fn remove_subscriber<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: &WebsocketMailbox,
+    ) -> Result<bool, message_broker::Error> {
+        dispatch!(self, broker => broker.remove_subscriber(topic, subscriber))
+    }
+
+    fn list_channels(&self) -> Vec<String> {
+        dispatch!(self, broker => broker.list_channels())
+    }
+
+    fn subscriber_count<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+    ) -> Result<usize, message_broker::Error> {
+        dispatch!(self, broker => broker.subscriber_count(topic))
+    }
+
+    fn channels_for(&self, subscriber: &WebsocketMailbox) -> Vec<String> {
+        dispatch!(self, broker => broker.channels_for(subscriber))
+    }
+
+    fn add_subscriber_with<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        subscriber: WebsocketMailbox,
+        options: SubscribeOptions<BrokerMessage>,
+    ) -> Result<Vec<BrokerMessage>, message_broker::Error> {
+        dispatch!(self, broker => broker.add_subscriber_with(topic, subscriber, options))
+    }
+}
+
+#[async_trait::async_trait]
+impl BrokerSend<ServerMessage, WebsocketMailbox, BrokerMessage> for RuimBroker {
+    async fn send_message<K: Topic + ?Sized>(
+        &self,
+        topic: &K,
+        message: BrokerMessage,
+    ) -> Result<DeliveryReport<WebsocketMailbox>, message_broker::Error> {
+        dispatch!(self, broker => broker.send_message(topic, message).await)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BrokerMessage {
+    id: String,
+    time: chrono::DateTime<chrono::Utc>,
+    payload: ServerMessage,
+}
+
+impl BrokerMessage {
+    pub fn new(payload: ServerMessage) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            time: chrono::Utc::now(),
+            payload,
+        }
+    }
+}
+
+impl message_broker::Message<ServerMessage> for BrokerMessage {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn time(&self) -> chrono::DateTime<chrono::Utc> {
+        self.time
+    }
+
+    fn payload(&self) -> &ServerMessage {
+        &self.payload
+    }
+}
+
+#[async_trait::async_trait]
+impl message_broker::Subscriber<ServerMessage, BrokerMessage> for SafeWebsocket {
+    async fn on_message(&self, message: &BrokerMessage) -> Result<(), message_broker::Error> {
+        let text = serde_json::to_string(&message.payload)
+            .map_err(|err| message_broker::Error::Other(err.to_string()))?;
+        let msg = axum::extract::ws::Message::Text(text);
+        let command = match &message.payload {
+            ServerMessage::Regular(body) => WebsocketControlMessage::SendStored {
+                msg,
+                message_id: body.message_id,
+            },
+            _ => WebsocketControlMessage::SendMessage(msg),
+        };
+
+        self.send_command(command)
+            .await
+            .map_err(|err| message_broker::Error::SubscriberGoneBad(err.to_string()))
+    }
+}
+
+/// Every topic the server publishes to, so routing typos fail to compile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RuimTopic {
+    /// Everything addressed to one user, every connection of theirs listens here.
+    UserInbox(Uuid),
+    /// The public lobby, every connection of a user shown in public chat listens here.
+    Lobby,
+}
+
+impl std::fmt::Display for RuimTopic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UserInbox(user_id) => write!(f, "user.{user_id}.inbox"),
+            Self::Lobby => write!(f, "lobby"),
+        }
+    }
+}
+
+impl Topic for RuimTopic {}
+
+/// Subscribes the websocket to its user's inbox, the broker creates the topic on first connect.
+/// The returned mailbox closes when the client falls too far behind.
+pub fn subscribe_user(
+    broker: &RuimBroker,
+    user_id: Uuid,
+    websocket: SafeWebsocket,
+) -> Result<WebsocketMailbox, message_broker::Error> {
+    let mailbox = Mailbox::new(websocket, MAILBOX_CONFIG);
+    broker.add_subscriber(&RuimTopic::UserInbox(user_id), mailbox.clone())?;
+
+    Ok(mailbox)
+}
+
+/// Lets the websocket's mailbox also receive the public lobby.
+pub fn join_lobby(
+    broker: &RuimBroker,
+    mailbox: &WebsocketMailbox,
+) -> Result<(), message_broker::Error> {
+    broker.add_subscriber(&RuimTopic::Lobby, mailbox.clone())
+}
+
+/// Broadcasts `payload` to whoever is in the lobby right now, nothing is queued.
+pub async fn publish_lobby(broker: &RuimBroker, payload: ServerMessage) -> anyhow::Result<()> {
+    match broker
+        .send_message(&RuimTopic::Lobby, BrokerMessage::new(payload))
+        .await
+    {
+        Ok(_) | Err(message_broker::Error::ChannelDoesNotExist(_)) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Detaches the websocket from its user's inbox, and the lobby if it joined, and closes its mailbox.
+/// The inbox itself stays; publishing to an inbox nobody listens on queues the message.
+pub fn unsubscribe_user(broker: &RuimBroker, user_id: Uuid, mailbox: &WebsocketMailbox) {
+    let _ = broker.remove_subscriber(&RuimTopic::UserInbox(user_id), mailbox);
+    let _ = broker.remove_subscriber(&RuimTopic::Lobby, mailbox);
+    mailbox.close();
+
+    let metrics = mailbox.metrics();
+    tracing::debug!(
+        %user_id,
+        delivered = metrics.delivered,
+        dropped = metrics.dropped,
+        depth = metrics.depth,
+        "websocket mailbox closed"
+    );
+}
+
+/// Whether `user_id` may have a live connection. The in-memory broker serves this process
+/// only, so the session manager knows for sure; with a shared broker the user may be
+/// connected to another instance.
+pub fn may_be_online(broker: &RuimBroker, session_manager: &SessionManager, user_id: Uuid) -> bool {
+    match broker {
+        RuimBroker::InMemory(_) => !session_manager.sessions(user_id).is_empty(),
+        RuimBroker::Resp(_) => true,
+    }
+}
+
+/// Publishes `payload` to the user's inbox if they are connected, nothing is queued.
+/// Returns whether any connection, here or on another instance, may have taken it.
+pub async fn publish_ephemeral(
+    broker: &RuimBroker,
+    user_id: Uuid,
+    payload: ServerMessage,
+) -> anyhow::Result<bool> {
+    match broker
+        .send_message(&RuimTopic::UserInbox(user_id), BrokerMessage::new(payload))
+        .await
+    {
+        Ok(report) => Ok(report.delivered() > 0 || report.remote_receivers > 0),
+        Err(message_broker::Error::ChannelDoesNotExist(_)) => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Publishes `payload` to the user's inbox.
+/// When no connection of the user takes the message it is queued in the session manager
+/// and sent once they reconnect.
+///
+/// A local connection taking the message is [`DeliveryStatus::Accepted`]. With a shared broker
+/// the server merely reports how many instances listen on the inbox, which is
+/// [`DeliveryStatus::Forwarded`]. Either way the message is only delivered once a websocket
+/// wrote it, see [`crate::core::receipt::spawn`].
+pub async fn publish_to_user(
+    broker: &RuimBroker,
+    session_manager: &SessionManager,
+    user_id: Uuid,
+    payload: ServerMessage,
+) -> anyhow::Result<DeliveryStatus> {
+    let text = serde_json::to_string(&payload)?;
+    let message_id = match &payload {
+        ServerMessage::Regular(body) => Some(body.message_id),
+        _ => None,
+    };
+
+    let status = match broker
+        .send_message(&RuimTopic::UserInbox(user_id), BrokerMessage::new(payload))
+        .await
+    {
+        Ok(report) => {
+            for (_, outcome) in report.outcomes.iter().filter(|(_, o)| !o.is_delivered()) {
+                tracing::debug!(%user_id, ?outcome, "evicted websocket from user inbox");
+            }
+            if report.delivered() > 0 {
+                Some(DeliveryStatus::Accepted)
+            } else if report.remote_receivers > 0 {
+                Some(DeliveryStatus::Forwarded)
+            } else {
+                None
+            }
+        }
+        Err(message_broker::Error::ChannelDoesNotExist(_)) => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    if let Some(status) = status {
+        return Ok(status);
+    }
+
+    session_manager.queue_message(user_id, axum::extract::ws::Message::Text(text), message_id);
+    Ok(DeliveryStatus::Queued)
+}
+
+impl FromRef<RuimContext> for RuimBroker {
+    fn from_ref(input: &RuimContext) -> Self {
+        input.broker.clone()
+    }
+}
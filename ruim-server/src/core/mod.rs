@@ -1 +1,6 @@
+pub mod broker;
+pub mod presence;
+pub mod receipt;
+pub mod room;
 pub mod session_manager;
+pub mod typing;
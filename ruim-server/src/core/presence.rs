@@ -0,0 +1,207 @@
+use std::{collections::HashMap, sync::Arc};
+
+use api_models::chat::{PresenceBody, PresenceStatus, ServerMessage};
+use axum::extract::FromRef;
+use dashmap::DashMap;
+use uuid::Uuid;
+
+use crate::{context::RuimContext, db::Database};
+
+use super::{
+    broker::{self, RuimBroker},
+    session_manager::SessionEvent,
+};
+
+/// Who is connected to this server instance, and whether they are active.
+/// Fed by the [`SessionEvent`]s of the session manager, see [`spawn`].
+#[derive(Clone, Default)]
+pub struct Presence {
+    /// Sessions by user id, then whether each connection is away.
+    sessions: Arc<DashMap<Uuid, HashMap<Uuid, bool>>>,
+}
+
+impl Presence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Online while any session is active, away while all of them are.
+    pub fn status(&self, user_id: Uuid) -> PresenceStatus {
+        match self.sessions.get(&user_id) {
+            None => PresenceStatus::Offline,
+            Some(sessions) if sessions.values().all(|away| *away) => PresenceStatus::Away,
+            Some(_) => PresenceStatus::Online,
+        }
+    }
+
+    /// Records `event` and returns the user's status if it changed.
+    pub fn apply(&self, event: SessionEvent) -> Option<(Uuid, PresenceStatus)> {
+        let (user_id, connection_id, away) = match event {
+            SessionEvent::Connected {
+                user_id,
+                connection_id,
+            }
+            | SessionEvent::Active {
+                user_id,
+                connection_id,
+            } => (user_id, connection_id, Some(false)),
+            SessionEvent::Away {
+                user_id,
+                connection_id,
+            } => (user_id, connection_id, Some(true)),
+            SessionEvent::Disconnected {
+                user_id,
+                connection_id,
+            } => (user_id, connection_id, None),
+        };
+
+        let before = self.status(user_id);
+        match away {
+            Some(away) => {
+                let mut sessions = self.sessions.entry(user_id).or_default();
+                // an away or active event may trail the disconnect of its session
+                if matches!(event, SessionEvent::Connected { .. })
+                    || sessions.contains_key(&connection_id)
+                {
+                    sessions.insert(connection_id, away);
+                }
+            }
+            None => {
+                self.sessions.remove_if_mut(&user_id, |_, sessions| {
+                    sessions.remove(&connection_id);
+                    sessions.is_empty()
+                });
+            }
+        }
+        self.sessions
+            .remove_if(&user_id, |_, sessions| sessions.is_empty());
+
+        let after = self.status(user_id);
+        (before != after).then_some((user_id, after))
+    }
+}
+
+impl FromRef<RuimContext> for Presence {
+    fn from_ref(input: &RuimContext) -> Self {
+        input.presence.clone()
+    }
+}
+
+/// Tracks presence from `events`. Every change updates the user's `last_seen`
+/// and is pushed to their friends who are connected.
+pub fn spawn(
+    presence: Presence,
+    mut events: tokio::sync::mpsc::UnboundedReceiver<SessionEvent>,
+    db: Database,
+    broker: RuimBroker,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let Some((user_id, status)) = presence.apply(event) else {
+                continue;
+            };
+            tracing::debug!(%user_id, ?status, "presence changed");
+            let _ = announce(&db, &broker, user_id, status)
+                .await
+                .inspect_err(|err| tracing::warn!(%user_id, ?err, "failed to announce presence"));
+        }
+    })
+}
+
+async fn announce(
+    db: &Database,
+    broker: &RuimBroker,
+    user_id: Uuid,
+    status: PresenceStatus,
+) -> anyhow::Result<()> {
+    let last_seen = db.touch_last_seen(user_id).await?;
+    let body = PresenceBody {
+        user_id,
+        status,
+        last_seen: last_seen.map(|t| t.to_string()),
+    };
+
+    for friend_id in db.query_friend_ids(user_id).await? {
+        broker::publish_ephemeral(broker, friend_id, ServerMessage::Presence(body.clone())).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected(user_id: Uuid, connection_id: Uuid) -> SessionEvent {
+        SessionEvent::Connected {
+            user_id,
+            connection_id,
+        }
+    }
+
+    #[test]
+    fn test_status_follows_all_sessions() {
+        let presence = Presence::new();
+        let user_id = Uuid::new_v4();
+        let (laptop, phone) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert_eq!(
+            presence.apply(connected(user_id, laptop)),
+            Some((user_id, PresenceStatus::Online))
+        );
+        assert_eq!(presence.apply(connected(user_id, phone)), None);
+
+        let away = |connection_id| SessionEvent::Away {
+            user_id,
+            connection_id,
+        };
+        assert_eq!(presence.apply(away(laptop)), None);
+        assert_eq!(
+            presence.apply(away(phone)),
+            Some((user_id, PresenceStatus::Away))
+        );
+        assert_eq!(
+            presence.apply(SessionEvent::Active {
+                user_id,
+                connection_id: laptop
+            }),
+            Some((user_id, PresenceStatus::Online))
+        );
+
+        let disconnected = |connection_id| SessionEvent::Disconnected {
+            user_id,
+            connection_id,
+        };
+        // the phone is still connected, but away
+        assert_eq!(
+            presence.apply(disconnected(laptop)),
+            Some((user_id, PresenceStatus::Away))
+        );
+        assert_eq!(
+            presence.apply(disconnected(phone)),
+            Some((user_id, PresenceStatus::Offline))
+        );
+        assert_eq!(presence.status(user_id), PresenceStatus::Offline);
+    }
+
+    #[test]
+    fn test_late_activity_does_not_revive_session() {
+        let presence = Presence::new();
+        let user_id = Uuid::new_v4();
+        let connection_id = Uuid::new_v4();
+
+        presence.apply(connected(user_id, connection_id));
+        presence.apply(SessionEvent::Disconnected {
+            user_id,
+            connection_id,
+        });
+        assert_eq!(
+            presence.apply(SessionEvent::Away {
+                user_id,
+                connection_id
+            }),
+            None
+        );
+        assert_eq!(presence.status(user_id), PresenceStatus::Offline);
+    }
+}
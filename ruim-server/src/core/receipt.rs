@@ -0,0 +1,69 @@
+use api_models::chat::{ReceiptBody, ReceiptStatus, ServerMessage};
+use uuid::Uuid;
+
+use crate::db::Database;
+
+use super::{
+    broker::{self, RuimBroker},
+    session_manager::{Delivery, SessionManager},
+};
+
+/// Marks each [`Delivery`] reported by the session manager in the database and tells
+/// the sender, the first time a message reaches any device of its receiver.
+pub fn spawn(
+    mut deliveries: tokio::sync::mpsc::UnboundedReceiver<Delivery>,
+    db: Database,
+    broker: RuimBroker,
+    session_manager: SessionManager,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(delivery) = deliveries.recv().await {
+            let _ = delivered(&db, &broker, &session_manager, delivery)
+                .await
+                .inspect_err(|err| tracing::warn!(?delivery, ?err, "failed to record delivery"));
+        }
+    })
+}
+
+async fn delivered(
+    db: &Database,
+    broker: &RuimBroker,
+    session_manager: &SessionManager,
+    Delivery {
+        receiver_id,
+        message_id,
+    }: Delivery,
+) -> anyhow::Result<()> {
+    let Some(sender_id) = db.mark_delivered(receiver_id, message_id).await? else {
+        return Ok(());
+    };
+
+    let receipt = receipt(receiver_id, message_id, ReceiptStatus::Delivered);
+    broker::publish_to_user(broker, session_manager, sender_id, receipt).await?;
+    Ok(())
+}
+
+/// Handles a `MarkRead` of `reader_id`, the sender hears about it unless nothing changed.
+pub async fn mark_read(
+    db: &Database,
+    broker: &RuimBroker,
+    session_manager: &SessionManager,
+    reader_id: Uuid,
+    up_to_message_id: i32,
+) -> anyhow::Result<()> {
+    let Some(sender_id) = db.mark_read(reader_id, up_to_message_id).await? else {
+        return Ok(());
+    };
+
+    let receipt = receipt(reader_id, up_to_message_id, ReceiptStatus::Read);
+    broker::publish_to_user(broker, session_manager, sender_id, receipt).await?;
+    Ok(())
+}
+
+fn receipt(peer_id: Uuid, up_to_message_id: i32, status: ReceiptStatus) -> ServerMessage {
+    ServerMessage::Receipt(ReceiptBody {
+        peer_id,
+        up_to_message_id,
+        status,
+    })
+}
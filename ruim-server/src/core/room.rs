@@ -0,0 +1,43 @@
+use api_models::{chat::ServerMessage, room::ClientRoomMessageBody};
+use uuid::Uuid;
+
+use crate::db::Database;
+
+use super::{
+    broker::{self, RuimBroker},
+    session_manager::SessionManager,
+};
+
+/// Stores the message and pushes it to every online member, the sender's own devices included.
+/// Members who are offline read it from the room history, see [`broker::may_be_online`].
+/// Membership changes while members are connected, so each message goes to the inboxes
+/// of the current members rather than to a room topic connections would have to track.
+/// Returns `false`, storing nothing, if `sender_id` is not a member of the room.
+pub async fn send_message(
+    db: &Database,
+    broker: &RuimBroker,
+    session_manager: &SessionManager,
+    sender_id: Uuid,
+    msg: ClientRoomMessageBody,
+) -> anyhow::Result<bool> {
+    if db.get_room_role(msg.room_id, sender_id).await?.is_none() {
+        return Ok(false);
+    }
+
+    let message = db
+        .add_room_message(msg.room_id, sender_id, &msg.message)
+        .await?;
+    let payload = ServerMessage::Room(message.into());
+
+    let online = db
+        .get_room_members(msg.room_id)
+        .await?
+        .into_iter()
+        .filter(|member| broker::may_be_online(broker, session_manager, member.user_id));
+    for member in online {
+        let _ = broker::publish_ephemeral(broker, member.user_id, payload.clone())
+            .await
+            .inspect_err(|err| tracing::warn!(?err, user_id = %member.user_id, "failed to fan out room message"));
+    }
+    Ok(true)
+}
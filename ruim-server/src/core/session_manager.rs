@@ -1,22 +1,56 @@
 use anyhow::Context;
-use axum::extract::{ws::WebSocket, FromRef};
+use axum::extract::{
+    ws::{close_code, WebSocket},
+    FromRef,
+};
 
+use api_models::chat::ChatSession;
 use dashmap::DashMap;
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use uuid::Uuid;
 
 use crate::context::RuimContext;
 
+/// How many undelivered messages are kept for a user while they are offline.
+/// The oldest message is dropped once the queue is full; it is still in the database.
+const MAX_PENDING_MESSAGES: usize = 256;
+
 /// To safely close the websocket, websocket need to take ownership.
 /// This means that it is impossible to have websocket in multiple places.
 /// hence we spwan a task to handle the websocket and communicate with it using channels.
 #[derive(Debug, Clone)]
 pub struct SafeWebsocket {
+    connection_id: Uuid,
     command_sender: tokio::sync::mpsc::Sender<WebsocketControlMessage>,
 }
 
+impl PartialEq for SafeWebsocket {
+    fn eq(&self, other: &Self) -> bool {
+        self.connection_id == other.connection_id
+    }
+}
+
+impl Eq for SafeWebsocket {}
+
+impl std::hash::Hash for SafeWebsocket {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.connection_id.hash(state);
+    }
+}
+
 impl SafeWebsocket {
+    pub fn connection_id(&self) -> Uuid {
+        self.connection_id
+    }
+
     pub async fn send_command(&self, msg: WebsocketControlMessage) -> anyhow::Result<()> {
         self.command_sender.send(msg).await?;
 
@@ -24,10 +58,154 @@ impl SafeWebsocket {
     }
 }
 
+/// How the server notices connections that went away without closing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// How often the server pings every websocket.
+    pub ping_interval: Duration,
+    /// Pings in a row left unanswered before the connection is considered dead.
+    pub max_missed_pongs: u32,
+    /// Closes connections the client sent nothing on for this long; pongs do not count.
+    pub idle_timeout: Duration,
+    /// Reports a session as away after this long without client traffic,
+    /// checked on every ping.
+    pub away_after: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(30),
+            max_missed_pongs: 2,
+            idle_timeout: Duration::from_secs(60 * 60),
+            away_after: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    /// Reads `HEARTBEAT_INTERVAL_SECS`, `HEARTBEAT_MAX_MISSED_PONGS`, `IDLE_TIMEOUT_SECS`
+    /// and `AWAY_AFTER_SECS`, any of them left unset or empty keeps its default.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let parse = |key: &str| -> anyhow::Result<Option<u64>> {
+            lookup(key)
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse().with_context(|| format!("invalid {key}")))
+                .transpose()
+        };
+
+        let mut config = Self::default();
+        if let Some(secs) = parse("HEARTBEAT_INTERVAL_SECS")? {
+            anyhow::ensure!(secs > 0, "HEARTBEAT_INTERVAL_SECS must be positive");
+            config.ping_interval = Duration::from_secs(secs);
+        }
+        if let Some(missed) = parse("HEARTBEAT_MAX_MISSED_PONGS")? {
+            config.max_missed_pongs = missed.try_into()?;
+        }
+        if let Some(secs) = parse("IDLE_TIMEOUT_SECS")? {
+            config.idle_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = parse("AWAY_AFTER_SECS")? {
+            config.away_after = Duration::from_secs(secs);
+        }
+        Ok(config)
+    }
+}
+
+/// Removes the session when the websocket task ends, however it ends.
+struct SessionGuard {
+    session_manager: SessionManager,
+    user_id: Uuid,
+    connection_id: Uuid,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.session_manager
+            .remove_websocket(self.user_id, self.connection_id);
+        self.session_manager.emit(SessionEvent::Disconnected {
+            user_id: self.user_id,
+            connection_id: self.connection_id,
+        });
+        tracing::debug!(user_id = %self.user_id, connection_id = %self.connection_id, "session removed");
+    }
+}
+
+/// What happened to a session, consumed by the presence tracker in `core::presence`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    Connected {
+        user_id: Uuid,
+        connection_id: Uuid,
+    },
+    Disconnected {
+        user_id: Uuid,
+        connection_id: Uuid,
+    },
+    /// The client sent nothing for [`HeartbeatConfig::away_after`].
+    Away {
+        user_id: Uuid,
+        connection_id: Uuid,
+    },
+    /// The client sent something again after being away.
+    Active {
+        user_id: Uuid,
+        connection_id: Uuid,
+    },
+}
+
+/// A stored direct message that was written to a websocket of its receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delivery {
+    pub receiver_id: Uuid,
+    pub message_id: i32,
+}
+
+/// A live websocket of a user, one per connected device.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub websocket: SafeWebsocket,
+    pub connected_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A message waiting in [`SessionManager::pending_messages`].
+#[derive(Clone)]
+pub struct PendingMessage {
+    pub msg: axum::extract::ws::Message,
+    /// The stored direct message it carries, if any, so it can be marked delivered once written.
+    pub message_id: Option<i32>,
+}
+
+/// Live sessions by user id, then by connection id.
+pub type Sessions = HashMap<Uuid, Session>;
+
 #[derive(Clone)]
 pub struct SessionManager {
-    // Maybe Mutex<HashMap> would be better?
-    pub websockets: Arc<DashMap<Uuid, SafeWebsocket>>,
+    /// Every connected device of a user has its own session, none replaces another.
+    pub websockets: Arc<DashMap<Uuid, Sessions>>,
+    /// Messages routed to a user that had no live websocket, flushed on reconnect.
+    pub pending_messages: Arc<DashMap<Uuid, VecDeque<PendingMessage>>>,
+    heartbeat: HeartbeatConfig,
+    shutting_down: Arc<AtomicBool>,
+    /// Number of running websocket handlers, see [`Self::track_handler`].
+    active_handlers: Arc<tokio::sync::watch::Sender<usize>>,
+    events: Option<tokio::sync::mpsc::UnboundedSender<SessionEvent>>,
+    deliveries: Option<tokio::sync::mpsc::UnboundedSender<Delivery>>,
+}
+
+/// Held by a websocket handler for as long as it runs, so shutdown can wait for it.
+pub struct HandlerGuard {
+    active_handlers: Arc<tokio::sync::watch::Sender<usize>>,
+}
+
+impl Drop for HandlerGuard {
+    fn drop(&mut self) {
+        self.active_handlers.send_modify(|count| *count -= 1);
+    }
 }
 
 impl Default for SessionManager {
@@ -40,8 +218,46 @@ impl SessionManager {
     pub fn new() -> Self {
         Self {
             websockets: Arc::new(DashMap::new()),
+            pending_messages: Arc::new(DashMap::new()),
+            heartbeat: HeartbeatConfig::default(),
+            shutting_down: Arc::default(),
+            active_handlers: Arc::new(tokio::sync::watch::channel(0).0),
+            events: None,
+            deliveries: None,
+        }
+    }
+
+    /// Reports every [`SessionEvent`] to `events`.
+    pub fn with_events(mut self, events: tokio::sync::mpsc::UnboundedSender<SessionEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Reports every [`Delivery`] to `deliveries`, consumed by `core::receipt`.
+    pub fn with_deliveries(
+        mut self,
+        deliveries: tokio::sync::mpsc::UnboundedSender<Delivery>,
+    ) -> Self {
+        self.deliveries = Some(deliveries);
+        self
+    }
+
+    fn report_delivery(&self, delivery: Delivery) {
+        if let Some(deliveries) = &self.deliveries {
+            let _ = deliveries.send(delivery);
         }
     }
+
+    fn emit(&self, event: SessionEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
 }
 
 impl SessionManager {
@@ -50,19 +266,53 @@ impl SessionManager {
         user_id: Uuid,
         mut websocket: WebSocket,
     ) -> (
+        SafeWebsocket,
         tokio::task::JoinHandle<()>,
         tokio::sync::mpsc::Receiver<WebsocketClientMessage>,
     ) {
+        // broker traffic is buffered by the websocket's mailbox, see `core::broker`
         let (command_sender, mut command_receiver) =
             tokio::sync::mpsc::channel::<WebsocketControlMessage>(1);
         let (client_sender, client_receiver) =
             tokio::sync::mpsc::channel::<WebsocketClientMessage>(10);
 
+        let connection_id = Uuid::new_v4();
+        let heartbeat = self.heartbeat;
+        let guard = SessionGuard {
+            session_manager: self.clone(),
+            user_id,
+            connection_id,
+        };
+
+        let safe_websocket = SafeWebsocket {
+            connection_id,
+            command_sender,
+        };
+        self.insert_session(user_id, safe_websocket.clone());
+        self.emit(SessionEvent::Connected {
+            user_id,
+            connection_id,
+        });
+
         let handle = tokio::spawn(async move {
+            // whichever way the loop ends, the session goes with it
+            let guard = guard;
+            let mut ping_interval = tokio::time::interval_at(
+                tokio::time::Instant::now() + heartbeat.ping_interval,
+                heartbeat.ping_interval,
+            );
+            ping_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut missed_pongs = 0;
+            let mut last_activity = tokio::time::Instant::now();
+            let mut away = false;
+            let idle_deadline = tokio::time::sleep(heartbeat.idle_timeout);
+            tokio::pin!(idle_deadline);
+
             loop {
                 tokio::select! {
                     msg = websocket.recv() => {
                         let Some(msg) = msg else {
+                            tracing::debug!(%user_id, %connection_id, "websocket closed by client");
                             let _ = client_sender.send(WebsocketClientMessage::Error).await.inspect_err(|err| {
                                 tracing::trace!("Error sending message to session manager: {:?}", err);
                             });
@@ -75,10 +325,51 @@ impl SessionManager {
                             break;
                         };
 
+                        match msg {
+                            // axum answers pings itself
+                            axum::extract::ws::Message::Ping(_) => continue,
+                            axum::extract::ws::Message::Pong(_) => {
+                                tracing::trace!(%user_id, %connection_id, "pong received");
+                                missed_pongs = 0;
+                                continue;
+                            }
+                            _ => {}
+                        }
+                        last_activity = tokio::time::Instant::now();
+                        idle_deadline.as_mut().reset(last_activity + heartbeat.idle_timeout);
+                        if away {
+                            away = false;
+                            tracing::debug!(%user_id, %connection_id, "websocket active again");
+                            guard.session_manager.emit(SessionEvent::Active { user_id, connection_id });
+                        }
+
                         let _ = client_sender.send(WebsocketClientMessage::Message(msg)).await.inspect_err(|err| {
                             tracing::error!("Error sending message to session manager: {:?}", err);
                         });
                     }
+                    _ = ping_interval.tick() => {
+                        if !away && last_activity.elapsed() >= heartbeat.away_after {
+                            away = true;
+                            tracing::debug!(%user_id, %connection_id, "websocket away");
+                            guard.session_manager.emit(SessionEvent::Away { user_id, connection_id });
+                        }
+                        if missed_pongs >= heartbeat.max_missed_pongs {
+                            tracing::warn!(%user_id, %connection_id, missed_pongs, "websocket stopped answering pings, closing");
+                            let _ = websocket.close().await;
+                            break;
+                        }
+                        tracing::trace!(%user_id, %connection_id, "sending ping");
+                        if let Err(err) = websocket.send(axum::extract::ws::Message::Ping(Vec::new())).await {
+                            tracing::debug!(%user_id, %connection_id, ?err, "failed to send ping, closing");
+                            break;
+                        }
+                        missed_pongs += 1;
+                    }
+                    _ = &mut idle_deadline => {
+                        tracing::info!(%user_id, %connection_id, idle_timeout = ?heartbeat.idle_timeout, "websocket idle, closing");
+                        let _ = websocket.close().await;
+                        break;
+                    }
                     command = command_receiver.recv() => {
                         let Some(command) = command else{
                             tracing::trace!("Command channel closed");
@@ -90,8 +381,15 @@ impl SessionManager {
                                     tracing::error!("Error sending message to websocket: {:?}", err);
                                 });
                             },
-                            WebsocketControlMessage::Close => {
-                                let _ = websocket.close().await.inspect_err(|err| {
+                            WebsocketControlMessage::SendStored { msg, message_id } => {
+                                match websocket.send(msg).await {
+                                    Ok(()) => guard.session_manager.report_delivery(Delivery { receiver_id: user_id, message_id }),
+                                    Err(err) => tracing::error!(message_id, "Error sending message to websocket: {:?}", err),
+                                }
+                            },
+                            WebsocketControlMessage::Close(close) => {
+                                tracing::debug!(%user_id, %connection_id, code = close.code, reason = %close.reason, "closing websocket on request");
+                                let _ = websocket.send(close.into()).await.inspect_err(|err| {
                                     tracing::error!("Error closing websocket: {:?}", err);
                                 });
                                 return;
@@ -102,34 +400,202 @@ impl SessionManager {
             }
         });
 
+        (safe_websocket, handle, client_receiver)
+    }
+
+    fn insert_session(&self, user_id: Uuid, websocket: SafeWebsocket) {
+        let session = Session {
+            websocket,
+            connected_at: chrono::Utc::now(),
+        };
         self.websockets
-            .insert(user_id, SafeWebsocket { command_sender });
+            .entry(user_id)
+            .or_default()
+            .insert(session.websocket.connection_id, session);
+    }
 
-        (handle, client_receiver)
+    /// Forgets one session; the user is dropped once their last session is gone.
+    /// Called when the session's websocket task ends, see [`Self::add_websocket`].
+    pub fn remove_websocket(&self, user_id: Uuid, connection_id: Uuid) {
+        self.websockets.remove_if_mut(&user_id, |_, sessions| {
+            sessions.remove(&connection_id);
+            sessions.is_empty()
+        });
     }
 
-    pub fn remove_websocket(&self, user_id: Uuid) {
-        self.websockets.remove(&user_id);
+    pub fn websocket(&self, user_id: Uuid, connection_id: Uuid) -> Option<SafeWebsocket> {
+        self.websockets
+            .get(&user_id)?
+            .get(&connection_id)
+            .map(|session| session.websocket.clone())
+    }
+
+    /// The user's sessions on this server instance, oldest first.
+    pub fn sessions(&self, user_id: Uuid) -> Vec<ChatSession> {
+        let mut sessions: Vec<_> = self
+            .websockets
+            .get(&user_id)
+            .map(|sessions| sessions.values().cloned().collect())
+            .unwrap_or_default();
+        sessions.sort_by_key(|session: &Session| session.connected_at);
+
+        sessions
+            .into_iter()
+            .map(|session| ChatSession {
+                connection_id: session.websocket.connection_id,
+                connected_at: session.connected_at.to_rfc3339(),
+            })
+            .collect()
     }
 
     pub async fn send_control_command(
         &self,
         user_id: Uuid,
+        connection_id: Uuid,
         command: WebsocketControlMessage,
     ) -> anyhow::Result<()> {
-        self.websockets
-            .get(&user_id)
+        self.websocket(user_id, connection_id)
             .context("missing websocket")?
             .send_command(command)
             .await?;
 
         Ok(())
     }
+
+    /// Closes one device's websocket, its handler then cleans up the session.
+    /// Returns `false` if the user has no such session here.
+    pub async fn kick_session(&self, user_id: Uuid, connection_id: Uuid) -> anyhow::Result<bool> {
+        let Some(websocket) = self.websocket(user_id, connection_id) else {
+            return Ok(false);
+        };
+        tracing::info!(%user_id, %connection_id, "kicking session");
+        websocket
+            .send_command(WebsocketControlMessage::Close(CloseMessage::new(
+                close_code::POLICY,
+                "Session closed from another device",
+            )))
+            .await?;
+
+        Ok(true)
+    }
+
+    pub fn track_handler(&self) -> HandlerGuard {
+        self.active_handlers.send_modify(|count| *count += 1);
+        HandlerGuard {
+            active_handlers: self.active_handlers.clone(),
+        }
+    }
+
+    /// Set once [`Self::shutdown`] started, new websockets are refused from then on.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Sends `close` to every session, then waits up to `deadline` for their handlers
+    /// to finish what they were doing, e.g. storing a message.
+    pub async fn shutdown(&self, close: CloseMessage, deadline: Duration) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+
+        let websockets: Vec<_> = self
+            .websockets
+            .iter()
+            .flat_map(|sessions| {
+                sessions
+                    .values()
+                    .map(|session| session.websocket.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        tracing::info!(sessions = websockets.len(), "closing all sessions");
+        for websocket in websockets {
+            let _ = websocket
+                .send_command(WebsocketControlMessage::Close(close.clone()))
+                .await;
+        }
+
+        let mut active_handlers = self.active_handlers.subscribe();
+        let drained = tokio::time::timeout(deadline, active_handlers.wait_for(|count| *count == 0))
+            .await
+            .is_ok();
+        if drained {
+            tracing::info!("all sessions drained");
+        } else {
+            tracing::warn!(
+                remaining = *self.active_handlers.borrow(),
+                ?deadline,
+                "shutdown deadline passed with sessions still running"
+            );
+        }
+    }
+
+    /// Keeps `msg` for a user with no live websocket until [`Self::flush_pending`].
+    pub fn queue_message(
+        &self,
+        user_id: Uuid,
+        msg: axum::extract::ws::Message,
+        message_id: Option<i32>,
+    ) {
+        let mut queue = self.pending_messages.entry(user_id).or_default();
+        if queue.len() >= MAX_PENDING_MESSAGES {
+            // a stored message stays undelivered, the client gets it from the history
+            let dropped = queue.pop_front().and_then(|pending| pending.message_id);
+            tracing::warn!(%user_id, ?dropped, "pending message queue full, dropping oldest");
+        }
+        queue.push_back(PendingMessage { msg, message_id });
+    }
+
+    /// Sends every queued message to the given session of the user, oldest first.
+    /// Whatever cannot be sent stays queued.
+    pub async fn flush_pending(&self, user_id: Uuid, connection_id: Uuid) -> anyhow::Result<()> {
+        let Some((_, mut queue)) = self.pending_messages.remove(&user_id) else {
+            return Ok(());
+        };
+
+        let websocket = self
+            .websocket(user_id, connection_id)
+            .context("missing websocket");
+
+        let result = async {
+            let websocket = websocket?;
+            while let Some(pending) = queue.pop_front() {
+                if let Err(err) = websocket.send_command(pending.clone().into()).await {
+                    queue.push_front(pending);
+                    return Err(err);
+                }
+            }
+            anyhow::Ok(())
+        }
+        .await;
+
+        if !queue.is_empty() {
+            // messages queued while flushing go after the ones we failed to send
+            let mut pending = self.pending_messages.entry(user_id).or_default();
+            queue.append(&mut *pending);
+            *pending = queue;
+        }
+
+        result
+    }
 }
 
 pub enum WebsocketControlMessage {
     SendMessage(axum::extract::ws::Message),
-    Close,
+    /// Carries a stored direct message; once it is written, a [`Delivery`] is reported.
+    SendStored {
+        msg: axum::extract::ws::Message,
+        message_id: i32,
+    },
+    /// Sends a close frame and ends the websocket task.
+    Close(CloseMessage),
+}
+
+impl From<PendingMessage> for WebsocketControlMessage {
+    fn from(PendingMessage { msg, message_id }: PendingMessage) -> Self {
+        match message_id {
+            Some(message_id) => Self::SendStored { msg, message_id },
+            None => Self::SendMessage(msg),
+        }
+    }
 }
 
 pub enum WebsocketClientMessage {
@@ -137,13 +603,226 @@ pub enum WebsocketClientMessage {
     Error,
 }
 
+/// Why the server closes a websocket, see [`close_code`] for the codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CloseMessage {
     pub code: u16,
     pub reason: String,
 }
 
+impl CloseMessage {
+    pub fn new(code: u16, reason: &str) -> Self {
+        Self {
+            code,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl From<CloseMessage> for axum::extract::ws::Message {
+    fn from(close: CloseMessage) -> Self {
+        axum::extract::ws::Message::Close(Some(axum::extract::ws::CloseFrame {
+            code: close.code,
+            reason: close.reason.into(),
+        }))
+    }
+}
+
 impl FromRef<RuimContext> for SessionManager {
     fn from_ref(input: &RuimContext) -> Self {
         input.session_manager.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_websocket() -> (
+        SafeWebsocket,
+        tokio::sync::mpsc::Receiver<WebsocketControlMessage>,
+    ) {
+        let (command_sender, command_receiver) = tokio::sync::mpsc::channel(1);
+        let websocket = SafeWebsocket {
+            connection_id: Uuid::new_v4(),
+            command_sender,
+        };
+        (websocket, command_receiver)
+    }
+
+    #[tokio::test]
+    async fn test_sessions_of_one_user_coexist() {
+        let manager = SessionManager::new();
+        let user_id = Uuid::new_v4();
+        let (laptop, _laptop_commands) = fake_websocket();
+        let (phone, _phone_commands) = fake_websocket();
+        manager.insert_session(user_id, laptop.clone());
+        manager.insert_session(user_id, phone.clone());
+
+        let sessions = manager.sessions(user_id);
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].connection_id, laptop.connection_id());
+
+        manager.remove_websocket(user_id, laptop.connection_id());
+        assert_eq!(manager.sessions(user_id).len(), 1);
+        manager.remove_websocket(user_id, phone.connection_id());
+        assert!(manager.websockets.get(&user_id).is_none());
+    }
+
+    #[test]
+    fn test_heartbeat_config_from_lookup() {
+        let env = HashMap::from([
+            ("HEARTBEAT_INTERVAL_SECS", "5"),
+            ("IDLE_TIMEOUT_SECS", "120"),
+        ]);
+        let config =
+            HeartbeatConfig::from_lookup(|key| env.get(key).map(|v| v.to_string())).unwrap();
+        assert_eq!(config.ping_interval, Duration::from_secs(5));
+        assert_eq!(config.idle_timeout, Duration::from_secs(120));
+        assert_eq!(
+            config.max_missed_pongs,
+            HeartbeatConfig::default().max_missed_pongs
+        );
+
+        let invalid = |key: &str| (key == "HEARTBEAT_INTERVAL_SECS").then(|| "0".to_string());
+        assert!(HeartbeatConfig::from_lookup(invalid).is_err());
+        let garbage = |key: &str| (key == "IDLE_TIMEOUT_SECS").then(|| "soon".to_string());
+        assert!(HeartbeatConfig::from_lookup(garbage).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_flush_pending_tags_stored_messages() {
+        let manager = SessionManager::new();
+        let user_id = Uuid::new_v4();
+        let text = || axum::extract::ws::Message::Text("hi".into());
+        for message_id in 0..MAX_PENDING_MESSAGES as i32 {
+            manager.queue_message(user_id, text(), Some(message_id));
+        }
+        manager.queue_message(user_id, text(), None);
+
+        // a closed session keeps everything queued
+        let (closed, commands) = fake_websocket();
+        drop(commands);
+        manager.insert_session(user_id, closed.clone());
+        assert!(manager
+            .flush_pending(user_id, closed.connection_id())
+            .await
+            .is_err());
+        assert_eq!(
+            manager.pending_messages.get(&user_id).unwrap().len(),
+            MAX_PENDING_MESSAGES
+        );
+
+        let (websocket, mut commands) = fake_websocket();
+        manager.insert_session(user_id, websocket.clone());
+        let received = tokio::spawn(async move {
+            let mut stored = Vec::new();
+            let mut plain = 0;
+            for _ in 0..MAX_PENDING_MESSAGES {
+                match commands.recv().await {
+                    Some(WebsocketControlMessage::SendStored { message_id, .. }) => {
+                        stored.push(message_id)
+                    }
+                    Some(WebsocketControlMessage::SendMessage(_)) => plain += 1,
+                    _ => panic!("expected a message"),
+                }
+            }
+            (stored, plain)
+        });
+        manager
+            .flush_pending(user_id, websocket.connection_id())
+            .await
+            .unwrap();
+
+        let (stored, plain) = received.await.unwrap();
+        // the oldest one was dropped when the queue overflowed
+        assert_eq!(stored, (1..MAX_PENDING_MESSAGES as i32).collect::<Vec<_>>());
+        assert_eq!(plain, 1);
+        assert!(manager.pending_messages.get(&user_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_session_guard_removes_session() {
+        let manager = SessionManager::new();
+        let user_id = Uuid::new_v4();
+        let (websocket, _commands) = fake_websocket();
+        manager.insert_session(user_id, websocket.clone());
+
+        drop(SessionGuard {
+            session_manager: manager.clone(),
+            user_id,
+            connection_id: websocket.connection_id(),
+        });
+        assert!(manager.sessions(user_id).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_closes_sessions_and_waits_for_handlers() {
+        let manager = SessionManager::new();
+        let (websocket, mut commands) = fake_websocket();
+        manager.insert_session(Uuid::new_v4(), websocket);
+        let handler = manager.track_handler();
+
+        let close = CloseMessage::new(close_code::AWAY, "Server is shutting down");
+        let shutdown = tokio::spawn({
+            let manager = manager.clone();
+            let close = close.clone();
+            async move { manager.shutdown(close, Duration::from_secs(10)).await }
+        });
+
+        let Some(WebsocketControlMessage::Close(received)) = commands.recv().await else {
+            panic!("expected a close command");
+        };
+        assert_eq!(received, close);
+        assert!(manager.is_shutting_down());
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!shutdown.is_finished());
+        drop(handler);
+        tokio::time::timeout(Duration::from_millis(10), shutdown)
+            .await
+            .expect("shutdown finishes once the handler is gone")
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_gives_up_after_deadline() {
+        let manager = SessionManager::new();
+        let _stuck = manager.track_handler();
+
+        let start = tokio::time::Instant::now();
+        manager
+            .shutdown(
+                CloseMessage::new(close_code::AWAY, "Server is shutting down"),
+                Duration::from_secs(5),
+            )
+            .await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_kick_closes_only_that_session() {
+        let manager = SessionManager::new();
+        let user_id = Uuid::new_v4();
+        let (laptop, mut laptop_commands) = fake_websocket();
+        let (phone, mut phone_commands) = fake_websocket();
+        manager.insert_session(user_id, laptop.clone());
+        manager.insert_session(user_id, phone);
+
+        assert!(manager
+            .kick_session(user_id, laptop.connection_id())
+            .await
+            .unwrap());
+        assert!(matches!(
+            laptop_commands.try_recv(),
+            Ok(WebsocketControlMessage::Close(_))
+        ));
+        assert!(phone_commands.try_recv().is_err());
+
+        // somebody else's session cannot be kicked
+        assert!(!manager
+            .kick_session(Uuid::new_v4(), laptop.connection_id())
+            .await
+            .unwrap());
+    }
+}
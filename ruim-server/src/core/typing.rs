@@ -0,0 +1,203 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use api_models::chat::{ServerMessage, TypingState, TYPING_TIMEOUT};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use super::broker::{self, RuimBroker};
+
+/// A connection's "started" is forwarded to the same receiver at most this often.
+const TYPING_REFRESH: Duration = Duration::from_secs(2);
+
+/// Where typing updates go: receiver id and state.
+pub type TypingSink = Arc<dyn Fn(Uuid, TypingState) + Send + Sync>;
+
+/// Typing indicators of one connection. Rate limits what is forwarded and sends
+/// "stopped" on the sender's behalf once an indicator expires.
+pub struct TypingTracker {
+    sink: TypingSink,
+    active: HashMap<Uuid, ActiveTyping>,
+}
+
+struct ActiveTyping {
+    forwarded_at: Instant,
+    /// Moved forward by every "started", the expiry task sleeps until it.
+    expires_at: Arc<Mutex<Instant>>,
+    expiry: tokio::task::JoinHandle<()>,
+}
+
+impl TypingTracker {
+    pub fn new(sink: TypingSink) -> Self {
+        Self {
+            sink,
+            active: HashMap::new(),
+        }
+    }
+
+    /// Publishes the indicators of `sender_id` to the receivers' inboxes, never queued.
+    pub fn for_user(broker: RuimBroker, sender_id: Uuid, username: String) -> Self {
+        Self::new(Arc::new(move |receiver_id, state| {
+            let broker = broker.clone();
+            let payload = ServerMessage::Typing {
+                sender_id: sender_id.to_string(),
+                username: username.clone(),
+                state,
+            };
+            tokio::spawn(async move {
+                let _ = broker::publish_ephemeral(&broker, receiver_id, payload)
+                    .await
+                    .inspect_err(|err| tracing::debug!(?err, "failed to publish typing"));
+            });
+        }))
+    }
+
+    pub fn update(&mut self, receiver_id: Uuid, state: TypingState) {
+        match state {
+            TypingState::Started => self.started(receiver_id),
+            TypingState::Stopped => {
+                if self.stop(receiver_id) {
+                    (self.sink)(receiver_id, TypingState::Stopped);
+                }
+            }
+        }
+    }
+
+    /// A message to `receiver_id` ends the indicator, clients clear it on the message itself.
+    pub fn message_sent(&mut self, receiver_id: Uuid) {
+        self.stop(receiver_id);
+    }
+
+    fn started(&mut self, receiver_id: Uuid) {
+        let now = Instant::now();
+        if let Some(active) = self
+            .active
+            .get_mut(&receiver_id)
+            .filter(|active| !active.expiry.is_finished())
+        {
+            *active.expires_at.lock().unwrap() = now + TYPING_TIMEOUT;
+            if now.duration_since(active.forwarded_at) < TYPING_REFRESH {
+                tracing::trace!(%receiver_id, "typing rate limited");
+                return;
+            }
+            active.forwarded_at = now;
+            (self.sink)(receiver_id, TypingState::Started);
+            return;
+        }
+
+        let expires_at = Arc::new(Mutex::new(now + TYPING_TIMEOUT));
+        let expiry = tokio::spawn({
+            let expires_at = expires_at.clone();
+            let sink = self.sink.clone();
+            async move {
+                loop {
+                    let deadline = *expires_at.lock().unwrap();
+                    tokio::time::sleep_until(deadline).await;
+                    if *expires_at.lock().unwrap() <= Instant::now() {
+                        break;
+                    }
+                }
+                tracing::trace!(%receiver_id, "typing expired");
+                sink(receiver_id, TypingState::Stopped);
+            }
+        });
+        self.active.insert(
+            receiver_id,
+            ActiveTyping {
+                forwarded_at: now,
+                expires_at,
+                expiry,
+            },
+        );
+        (self.sink)(receiver_id, TypingState::Started);
+    }
+
+    /// Returns whether an indicator was still running.
+    fn stop(&mut self, receiver_id: Uuid) -> bool {
+        let Some(active) = self.active.remove(&receiver_id) else {
+            return false;
+        };
+        let running = !active.expiry.is_finished();
+        active.expiry.abort();
+        running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Sent = Arc<Mutex<Vec<(Uuid, TypingState)>>>;
+
+    fn tracker() -> (TypingTracker, Sent) {
+        let sent = Sent::default();
+        let sink = {
+            let sent = sent.clone();
+            Arc::new(move |receiver_id, state| sent.lock().unwrap().push((receiver_id, state)))
+        };
+        (TypingTracker::new(sink), sent)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_started_is_rate_limited() {
+        let (mut tracker, sent) = tracker();
+        let receiver_id = Uuid::new_v4();
+
+        for _ in 0..5 {
+            tracker.update(receiver_id, TypingState::Started);
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        tracker.update(receiver_id, TypingState::Stopped);
+        tracker.update(receiver_id, TypingState::Stopped);
+
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![
+                (receiver_id, TypingState::Started),
+                (receiver_id, TypingState::Started),
+                (receiver_id, TypingState::Stopped),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_typing_expires_without_stop() {
+        let (mut tracker, sent) = tracker();
+        let receiver_id = Uuid::new_v4();
+
+        tracker.update(receiver_id, TypingState::Started);
+        tokio::time::sleep(TYPING_TIMEOUT - Duration::from_secs(1)).await;
+        // still typing, the deadline moves
+        tracker.update(receiver_id, TypingState::Started);
+        tokio::time::sleep(TYPING_TIMEOUT - Duration::from_secs(1)).await;
+        assert_eq!(sent.lock().unwrap().len(), 2);
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(
+            sent.lock().unwrap().last(),
+            Some(&(receiver_id, TypingState::Stopped))
+        );
+
+        // already expired, nothing left to stop
+        tracker.update(receiver_id, TypingState::Stopped);
+        assert_eq!(sent.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_message_ends_typing_silently() {
+        let (mut tracker, sent) = tracker();
+        let receiver_id = Uuid::new_v4();
+
+        tracker.update(receiver_id, TypingState::Started);
+        tracker.message_sent(receiver_id);
+        tokio::time::sleep(TYPING_TIMEOUT * 2).await;
+
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![(receiver_id, TypingState::Started)]
+        );
+    }
+}
@@ -1,23 +1,204 @@
+use api_models::chat::UnreadCount;
 use uuid::Uuid;
 
+use crate::model::message::{Conversation, Message};
+
 impl super::Database {
+    /// Stores a direct message and returns its `message_id`.
     pub async fn add_chat_message(
         &self,
         user_id: Uuid,
         receiver_id: Uuid,
         message: &str,
-    ) -> anyhow::Result<()> {
-        sqlx::query!(
+    ) -> anyhow::Result<i32> {
+        let res = sqlx::query!(
             r#"
             INSERT INTO messages (sender_id, receiver_id, content)
             VALUES ($1, $2, $3)
+            RETURNING message_id
             "#,
             user_id,
             receiver_id,
             message
         )
-        .execute(&self.pool)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(res.message_id)
+    }
+
+    /// Records that a websocket of `receiver_id` wrote the message.
+    /// Returns the sender unless it was delivered already, e.g. to another device.
+    pub async fn mark_delivered(
+        &self,
+        receiver_id: Uuid,
+        message_id: i32,
+    ) -> anyhow::Result<Option<Uuid>> {
+        let row = sqlx::query!(
+            r#"
+            UPDATE messages SET delivered_at = now()
+            WHERE message_id = $1 AND receiver_id = $2 AND delivered_at IS NULL
+            RETURNING sender_id
+            "#,
+            message_id,
+            receiver_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.and_then(|row| row.sender_id))
+    }
+
+    /// Marks the messages of the conversation `up_to_message_id` belongs to as read,
+    /// up to and including that one.
+    /// Returns the sender when anything was still unread, `None` when the message
+    /// was not sent to `reader_id`.
+    pub async fn mark_read(
+        &self,
+        reader_id: Uuid,
+        up_to_message_id: i32,
+    ) -> anyhow::Result<Option<Uuid>> {
+        let row = sqlx::query!(
+            r#"
+            WITH target AS (
+                SELECT sender_id FROM messages
+                WHERE message_id = $2 AND receiver_id = $1
+            ), marked AS (
+                UPDATE messages SET read_at = now(), delivered_at = COALESCE(delivered_at, now())
+                WHERE receiver_id = $1
+                    AND sender_id = (SELECT sender_id FROM target)
+                    AND message_id <= $2
+                    AND read_at IS NULL
+                RETURNING message_id
+            )
+            SELECT (SELECT sender_id FROM target) AS sender_id, COUNT(*) AS "marked!"
+            FROM marked
+            "#,
+            reader_id,
+            up_to_message_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.sender_id.filter(|_| row.marked > 0))
+    }
+
+    /// Unread messages per sender, for every conversation that has any.
+    pub async fn unread_counts(&self, user_id: Uuid) -> anyhow::Result<Vec<UnreadCount>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT sender_id AS "sender_id!", COUNT(*) AS "count!"
+            FROM messages
+            WHERE receiver_id = $1 AND read_at IS NULL AND sender_id IS NOT NULL
+            GROUP BY sender_id
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
         .await?;
-        Ok(())
+
+        Ok(rows
+            .into_iter()
+            .map(|row| UnreadCount {
+                peer_id: row.sender_id,
+                count: row.count,
+            })
+            .collect())
+    }
+
+    /// Up to `limit` messages between the two users, newest first,
+    /// starting after the message `before` if given.
+    /// A `before` that is not part of the conversation yields no messages.
+    pub async fn get_conversation_messages(
+        &self,
+        user_id: Uuid,
+        peer_id: Uuid,
+        before: Option<i32>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Message>> {
+        let messages = sqlx::query_as!(
+            Message,
+            r#"
+            WITH conversation AS (
+                SELECT * FROM messages
+                WHERE (sender_id = $1 AND receiver_id = $2)
+                    OR (sender_id = $2 AND receiver_id = $1)
+            )
+            SELECT
+                message_id,
+                sender_id AS "sender_id!",
+                receiver_id AS "receiver_id!",
+                content,
+                created_at,
+                delivered_at,
+                read_at
+            FROM conversation
+            WHERE $3::INT IS NULL
+                OR (created_at, message_id) < (
+                    SELECT created_at, message_id FROM conversation WHERE message_id = $3
+                )
+            ORDER BY created_at DESC, message_id DESC
+            LIMIT $4
+            "#,
+            user_id,
+            peer_id,
+            before,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// Every peer `user_id` has exchanged messages with, most recent conversation first.
+    pub async fn get_conversations(&self, user_id: Uuid) -> anyhow::Result<Vec<Conversation>> {
+        let rows = sqlx::query!(
+            r#"
+            WITH latest AS (
+                SELECT DISTINCT ON (peer_id) *
+                FROM (
+                    SELECT
+                        CASE WHEN sender_id = $1 THEN receiver_id ELSE sender_id END AS peer_id,
+                        message_id, sender_id, content, created_at
+                    FROM messages
+                    WHERE sender_id = $1 OR receiver_id = $1
+                ) AS mine
+                ORDER BY peer_id, created_at DESC, message_id DESC
+            ), unread AS (
+                SELECT sender_id, COUNT(*) AS count
+                FROM messages
+                WHERE receiver_id = $1 AND read_at IS NULL
+                GROUP BY sender_id
+            )
+            SELECT
+                users.user_id,
+                users.username,
+                latest.message_id AS "last_message_id!",
+                latest.sender_id AS "last_sender_id!",
+                LEFT(latest.content, $2) AS "last_message_preview!",
+                latest.created_at AS "last_message_at!",
+                COALESCE(unread.count, 0) AS "unread!"
+            FROM latest
+            JOIN users ON users.user_id = latest.peer_id
+            LEFT JOIN unread ON unread.sender_id = latest.peer_id
+            ORDER BY latest.created_at DESC, latest.message_id DESC
+            "#,
+            user_id,
+            api_models::chat::MESSAGE_PREVIEW_CHARS as i32
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Conversation {
+                peer_id: row.user_id,
+                peer_username: row.username,
+                last_message_id: row.last_message_id,
+                last_sender_id: row.last_sender_id,
+                last_message_preview: row.last_message_preview,
+                last_message_at: row.last_message_at,
+                unread: row.unread,
+            })
+            .collect())
     }
 }
@@ -1,4 +1,5 @@
 pub mod chat;
+mod room;
 mod user;
 use axum::extract::FromRef;
 use sqlx::postgres::PgPoolOptions;
@@ -0,0 +1,189 @@
+use uuid::Uuid;
+
+use crate::model::room::{Room, RoomMember, RoomMessage, RoomRole};
+
+use super::DBError;
+
+impl super::Database {
+    /// Creates the room with `owner_id` as its owner and only member.
+    pub async fn create_room(&self, owner_id: Uuid, name: &str) -> Result<Room, DBError> {
+        let mut tx = self.pool.begin().await.map_err(DBError::Sqlx)?;
+
+        let room = sqlx::query_as!(
+            Room,
+            r#"
+            INSERT INTO rooms (name, owner_id)
+            VALUES ($1, $2)
+            RETURNING *
+            "#,
+            name,
+            owner_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(DBError::Sqlx)?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO room_members (room_id, user_id, role)
+            VALUES ($1, $2, $3)
+            "#,
+            room.room_id,
+            owner_id,
+            i16::from(RoomRole::Owner)
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(DBError::Sqlx)?;
+
+        tx.commit().await.map_err(DBError::Sqlx)?;
+        Ok(room)
+    }
+
+    /// Every room `user_id` is a member of.
+    pub async fn get_rooms(&self, user_id: Uuid) -> Result<Vec<Room>, DBError> {
+        sqlx::query_as!(
+            Room,
+            r#"
+            SELECT rooms.* FROM rooms
+            JOIN room_members USING (room_id)
+            WHERE room_members.user_id = $1
+            ORDER BY rooms.created_at
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(DBError::Sqlx)
+    }
+
+    /// The role of `user_id` in the room, `None` if they are not a member or there is no such room.
+    pub async fn get_room_role(
+        &self,
+        room_id: i32,
+        user_id: Uuid,
+    ) -> Result<Option<RoomRole>, DBError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT role FROM room_members
+            WHERE room_id = $1 AND user_id = $2
+            "#,
+            room_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(DBError::Sqlx)?;
+
+        row.map(|row| RoomRole::try_from(row.role))
+            .transpose()
+            .map_err(|err| DBError::Other(err.into()))
+    }
+
+    pub async fn get_room_members(&self, room_id: i32) -> Result<Vec<RoomMember>, DBError> {
+        sqlx::query_as!(
+            RoomMember,
+            r#"
+            SELECT * FROM room_members
+            WHERE room_id = $1
+            ORDER BY role, joined_at
+            "#,
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(DBError::Sqlx)
+    }
+
+    /// Returns `false` if `user_id` already is a member.
+    pub async fn add_room_member(
+        &self,
+        room_id: i32,
+        user_id: Uuid,
+        role: RoomRole,
+    ) -> Result<bool, DBError> {
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO room_members (room_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+            room_id,
+            user_id,
+            i16::from(role)
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(DBError::Sqlx)?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Returns `false` if `user_id` was not a member.
+    pub async fn remove_room_member(&self, room_id: i32, user_id: Uuid) -> Result<bool, DBError> {
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM room_members
+            WHERE room_id = $1 AND user_id = $2
+            "#,
+            room_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(DBError::Sqlx)?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn add_room_message(
+        &self,
+        room_id: i32,
+        sender_id: Uuid,
+        message: &str,
+    ) -> Result<RoomMessage, DBError> {
+        sqlx::query_as!(
+            RoomMessage,
+            r#"
+            INSERT INTO room_messages (room_id, sender_id, content)
+            VALUES ($1, $2, $3)
+            RETURNING *
+            "#,
+            room_id,
+            sender_id,
+            message
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(DBError::Sqlx)
+    }
+
+    /// Up to `limit` messages of the room, newest first, starting after the message `before`
+    /// if given. A `before` from another room yields no messages.
+    pub async fn get_room_messages(
+        &self,
+        room_id: i32,
+        before: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<RoomMessage>, DBError> {
+        sqlx::query_as!(
+            RoomMessage,
+            r#"
+            SELECT * FROM room_messages
+            WHERE room_id = $1
+                AND ($2::INT IS NULL OR (created_at, message_id) < (
+                    SELECT created_at, message_id FROM room_messages
+                    WHERE room_id = $1 AND message_id = $2
+                ))
+            ORDER BY created_at DESC, message_id DESC
+            LIMIT $3
+            "#,
+            room_id,
+            before,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(DBError::Sqlx)
+    }
+}
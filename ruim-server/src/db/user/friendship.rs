@@ -41,4 +41,22 @@ impl crate::db::Database {
 
         Ok(res)
     }
+
+    /// Ids of everyone `user_id` is friends with, in either direction.
+    pub async fn query_friend_ids(&self, user_id: Uuid) -> Result<Vec<Uuid>, crate::db::DBError> {
+        let res = sqlx::query!(
+            r#"
+            SELECT CASE WHEN user1_id = $1 THEN user2_id ELSE user1_id END AS "friend_id!"
+            FROM friendships
+            WHERE (user1_id = $1 OR user2_id = $1) AND status = $2
+            "#,
+            user_id,
+            i16::from(crate::model::user::FriendShipStatus::Friend),
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(crate::db::DBError::Sqlx)?;
+
+        Ok(res.into_iter().map(|row| row.friend_id).collect())
+    }
 }
@@ -58,19 +58,19 @@ impl super::Database {
         Ok(user)
     }
 
+    /// Users who opted into the public directory, oldest accounts first, skipping the
+    /// first `offset`. The caller validates the offset.
     pub async fn get_public_users(
         &self,
-        page: i64,
+        offset: i64,
         limit: i64,
     ) -> Result<Vec<User>, super::DBError> {
-        let offset = (page - 1) * limit;
-
         let users = sqlx::query_as!(
             User,
             r#"
             SELECT * FROM users
-            WHERE accept_public_chat = true
-            ORDER BY created_at
+            WHERE show_in_public_chat = true
+            ORDER BY created_at, user_id
             LIMIT $1
             OFFSET $2
             "#,
@@ -83,4 +83,74 @@ impl super::Database {
 
         Ok(users)
     }
+
+    /// Whether `receiver_id` takes direct messages from `sender_id`: friends always,
+    /// anyone else only if the receiver accepts public chat. `None` if there is no such receiver.
+    pub async fn accepts_messages_from(
+        &self,
+        receiver_id: Uuid,
+        sender_id: Uuid,
+    ) -> Result<Option<bool>, super::DBError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT accept_public_chat OR EXISTS (
+                SELECT 1 FROM friendships
+                WHERE ((user1_id = $1 AND user2_id = $2) OR (user1_id = $2 AND user2_id = $1))
+                    AND status = $3
+            ) AS "accepts!"
+            FROM users
+            WHERE user_id = $1
+            "#,
+            receiver_id,
+            sender_id,
+            i16::from(crate::model::user::FriendShipStatus::Friend),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(super::DBError::Sqlx)?;
+
+        Ok(row.map(|row| row.accepts))
+    }
+
+    /// Sets `last_seen` to now and returns it.
+    pub async fn touch_last_seen(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<sqlx::types::time::OffsetDateTime>, super::DBError> {
+        let res = sqlx::query!(
+            r#"
+            UPDATE users SET last_seen = CURRENT_TIMESTAMP
+            WHERE user_id = $1
+            RETURNING last_seen
+            "#,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(super::DBError::Sqlx)?;
+
+        Ok(res.last_seen)
+    }
+
+    /// `last_seen` of every existing user in `user_ids`.
+    pub async fn get_last_seen(
+        &self,
+        user_ids: &[Uuid],
+    ) -> Result<Vec<(Uuid, Option<sqlx::types::time::OffsetDateTime>)>, super::DBError> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT user_id, last_seen FROM users
+            WHERE user_id = ANY($1)
+            "#,
+            user_ids
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(super::DBError::Sqlx)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.user_id, row.last_seen))
+            .collect())
+    }
 }
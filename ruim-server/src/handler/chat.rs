@@ -1,24 +1,45 @@
-use anyhow::Context;
+use std::{collections::HashMap, time::Duration};
+
 use axum::{
     extract::{
-        ws::{WebSocket, WebSocketUpgrade},
+        ws::{close_code, WebSocket, WebSocketUpgrade},
         State,
     },
+    http::StatusCode,
     response::IntoResponse,
 };
 use uuid::Uuid;
 
-use crate::{db::Database, service::auth::UserTokenExtractor};
+use crate::{
+    core::{
+        broker::{self, RuimBroker},
+        receipt, room,
+        session_manager::{CloseMessage, SafeWebsocket, WebsocketControlMessage},
+        typing::TypingTracker,
+    },
+    db::Database,
+    handler::ApiError,
+    service::auth::UserTokenExtractor,
+};
+
+/// How long a receiver's answer to "do you take messages from this sender" is reused
+/// for typing indicators, which arrive far more often than messages.
+const TYPING_PERMISSION_TTL: Duration = Duration::from_secs(30);
 
 pub async fn websocket_handler(
     UserTokenExtractor { user_id }: UserTokenExtractor,
     State(db): State<crate::db::Database>,
     State(session_manager): State<crate::core::session_manager::SessionManager>,
+    State(broker): State<RuimBroker>,
     ws: WebSocketUpgrade,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |ws| async move {
-        handle_socket(ws, user_id, db, session_manager).await;
-    })
+) -> Result<impl IntoResponse, ApiError> {
+    if session_manager.is_shutting_down() {
+        return Err(ApiError::msg("Server is shutting down").code(StatusCode::SERVICE_UNAVAILABLE));
+    }
+
+    Ok(ws.on_upgrade(move |ws| async move {
+        handle_socket(ws, user_id, db, session_manager, broker).await;
+    }))
 }
 
 async fn handle_socket(
@@ -26,12 +47,54 @@ async fn handle_socket(
     user_id: Uuid,
     db: Database,
     session_manager: crate::core::session_manager::SessionManager,
+    broker: RuimBroker,
 ) {
-    let (websocket_task_handle, mut client_receiver) =
+    let _handler = session_manager.track_handler();
+    let (websocket, websocket_task_handle, mut client_receiver) =
         session_manager.add_websocket(user_id, socket);
 
+    let mailbox = match broker::subscribe_user(&broker, user_id, websocket.clone()) {
+        Ok(mailbox) => mailbox,
+        Err(err) => {
+            tracing::error!(%user_id, ?err, "failed to subscribe websocket to user inbox");
+            let _ = websocket
+                .send_command(WebsocketControlMessage::Close(CloseMessage::new(
+                    close_code::ERROR,
+                    "Failed to subscribe to messages",
+                )))
+                .await;
+            return;
+        }
+    };
+
+    let user = db
+        .get_user_by_id(&user_id)
+        .await
+        .inspect_err(|err| tracing::warn!(%user_id, ?err, "failed to look up user"))
+        .ok()
+        .flatten();
+    // shown next to typing indicators
+    let username = user
+        .as_ref()
+        .map_or_else(|| user_id.to_string(), |user| user.username.clone());
+    // only users shown in public chat hear the lobby; a connection keeps hearing it until
+    // it reconnects, posting is checked against the current setting every time
+    if user.is_some_and(|user| user.show_in_public_chat) {
+        let _ = broker::join_lobby(&broker, &mailbox)
+            .inspect_err(|err| tracing::warn!(%user_id, ?err, "failed to join the lobby"));
+    }
+
+    let _ = session_manager
+        .flush_pending(user_id, websocket.connection_id())
+        .await
+        .inspect_err(|err| tracing::warn!(%user_id, ?err, "failed to flush pending messages"));
+
     let session_manager_clone = session_manager.clone();
-    let client_receive_handle = tokio::spawn(async move {
+    let broker_clone = broker.clone();
+    let websocket_clone = websocket.clone();
+    let mut client_receive_handle = tokio::spawn(async move {
+        let mut typing = TypingTracker::for_user(broker_clone.clone(), user_id, username);
+        let mut typing_permitted: HashMap<Uuid, (bool, tokio::time::Instant)> = HashMap::new();
         while let Some(msg) = client_receiver.recv().await {
             let crate::core::session_manager::WebsocketClientMessage::Message(msg) = msg else {
                 tracing::error!("Error receiving message from session manager");
@@ -45,31 +108,189 @@ async fn handle_socket(
 
             let msg: api_models::chat::ClientMessage = serde_json::from_str(&msg)?;
 
+            // a failing lookup or publish costs the client this one message, not the session
             match msg {
                 api_models::chat::ClientMessage::Regular(msg) => {
-                    db.add_chat_message(user_id, Uuid::parse_str(&msg.receiver_id)?, &msg.message)
+                    let rejected = |reason: &str| api_models::chat::RejectedBody {
+                        receiver_id: Some(msg.receiver_id.clone()),
+                        room_id: None,
+                        created_at: msg.created_at.clone(),
+                        reason: reason.to_string(),
+                    };
+                    let Ok(receiver_id) = Uuid::parse_str(&msg.receiver_id) else {
+                        send_rejected(&websocket_clone, rejected("Invalid receiver id")).await;
+                        continue;
+                    };
+                    typing.message_sent(receiver_id);
+
+                    let reason = match db.accepts_messages_from(receiver_id, user_id).await {
+                        Ok(Some(true)) => None,
+                        Ok(Some(false)) => Some("Only accepts messages from friends"),
+                        Ok(None) => Some("User not found"),
+                        Err(err) => {
+                            tracing::error!(?err, "Failed to check the receiver");
+                            Some("Failed to send the message")
+                        }
+                    };
+                    if let Some(reason) = reason {
+                        send_rejected(&websocket_clone, rejected(reason)).await;
+                        continue;
+                    }
+
+                    let message_id = match db
+                        .add_chat_message(user_id, receiver_id, &msg.message)
                         .await
-                        .context("Failed to add chat message")
-                        .inspect_err(|err| {
-                            tracing::error!(?err);
-                        })?;
+                    {
+                        Ok(message_id) => message_id,
+                        Err(err) => {
+                            tracing::error!(?err, "Failed to add chat message");
+                            send_rejected(&websocket_clone, rejected("Failed to send the message"))
+                                .await;
+                            continue;
+                        }
+                    };
 
                     let server_msg = api_models::chat::ServerMessage::Regular(
                         api_models::chat::ServerMessageBody {
+                            message_id,
                             message: msg.message,
-                            created_at: msg.created_at,
+                            created_at: msg.created_at.clone(),
                             sender_id: user_id.to_string(),
                         },
                     );
 
-                    let msg = axum::extract::ws::Message::Text(serde_json::to_string(&server_msg)?);
-                    let _ = session_manager_clone
-                        .send_control_command(
-                            user_id,
-                            crate::core::session_manager::WebsocketControlMessage::SendMessage(msg),
+                    let status = broker::publish_to_user(
+                        &broker_clone,
+                        &session_manager_clone,
+                        receiver_id,
+                        server_msg,
+                    )
+                    .await
+                    .unwrap_or_else(|err| {
+                        tracing::error!(?err, message_id, "Failed to publish chat message");
+                        // stored all the same, the receiver finds it in the history
+                        api_models::chat::DeliveryStatus::Queued
+                    });
+
+                    let ack = api_models::chat::ServerMessage::Ack(api_models::chat::AckBody {
+                        message_id,
+                        receiver_id: msg.receiver_id,
+                        created_at: msg.created_at,
+                        status,
+                    });
+                    let ack = axum::extract::ws::Message::Text(serde_json::to_string(&ack)?);
+                    let _ = websocket_clone
+                        .send_command(
+                            crate::core::session_manager::WebsocketControlMessage::SendMessage(ack),
                         )
                         .await;
                 }
+                api_models::chat::ClientMessage::Typing { receiver_id, state } => {
+                    let Ok(receiver_id) = Uuid::parse_str(&receiver_id) else {
+                        tracing::debug!(%user_id, "typing for an invalid receiver id");
+                        continue;
+                    };
+                    // typing goes only where a message would be accepted
+                    let permitted = match typing_permitted.get(&receiver_id) {
+                        Some((permitted, checked_at))
+                            if checked_at.elapsed() < TYPING_PERMISSION_TTL =>
+                        {
+                            *permitted
+                        }
+                        _ => match db.accepts_messages_from(receiver_id, user_id).await {
+                            Ok(accepts) => {
+                                let permitted = accepts.unwrap_or(false);
+                                typing_permitted
+                                    .insert(receiver_id, (permitted, tokio::time::Instant::now()));
+                                permitted
+                            }
+                            Err(err) => {
+                                tracing::warn!(?err, "Failed to check the typing receiver");
+                                false
+                            }
+                        },
+                    };
+                    if permitted {
+                        typing.update(receiver_id, state);
+                    }
+                }
+                api_models::chat::ClientMessage::MarkRead { up_to_message_id } => {
+                    let _ = receipt::mark_read(
+                        &db,
+                        &broker_clone,
+                        &session_manager_clone,
+                        user_id,
+                        up_to_message_id,
+                    )
+                    .await
+                    .inspect_err(|err| {
+                        tracing::error!(?err, "Failed to mark messages read");
+                    });
+                }
+                api_models::chat::ClientMessage::Room(msg) => {
+                    let room_id = msg.room_id;
+                    let created_at = msg.created_at.clone();
+                    let reason = match room::send_message(
+                        &db,
+                        &broker_clone,
+                        &session_manager_clone,
+                        user_id,
+                        msg,
+                    )
+                    .await
+                    {
+                        Ok(true) => continue,
+                        Ok(false) => {
+                            tracing::warn!(%user_id, room_id, "message to a room the user is not in");
+                            "Not a member of the room"
+                        }
+                        Err(err) => {
+                            tracing::error!(?err, "Failed to send room message");
+                            "Failed to send the message"
+                        }
+                    };
+                    let rejected = api_models::chat::RejectedBody {
+                        receiver_id: None,
+                        room_id: Some(room_id),
+                        created_at,
+                        reason: reason.to_string(),
+                    };
+                    send_rejected(&websocket_clone, rejected).await;
+                }
+                api_models::chat::ClientMessage::Lobby(msg) => {
+                    let shown = match db.get_user_by_id(&user_id).await {
+                        Ok(user) => user.filter(|user| user.show_in_public_chat),
+                        Err(err) => {
+                            tracing::error!(?err, "Failed to look up the lobby poster");
+                            None
+                        }
+                    };
+                    let Some(user) = shown else {
+                        tracing::warn!(%user_id, "lobby message from a user not shown in public chat");
+                        let rejected = api_models::chat::RejectedBody {
+                            receiver_id: None,
+                            room_id: None,
+                            created_at: msg.created_at,
+                            reason: "Only users shown in public chat may post in the lobby"
+                                .to_string(),
+                        };
+                        send_rejected(&websocket_clone, rejected).await;
+                        continue;
+                    };
+                    let lobby_msg = api_models::chat::ServerMessage::Lobby(
+                        api_models::chat::LobbyMessageBody {
+                            sender_id: user_id,
+                            username: user.username,
+                            message: msg.message,
+                            created_at: msg.created_at,
+                        },
+                    );
+                    let _ = broker::publish_lobby(&broker_clone, lobby_msg)
+                        .await
+                        .inspect_err(|err| {
+                            tracing::error!(?err, "Failed to publish lobby message");
+                        });
+                }
             }
         }
 
@@ -80,10 +301,37 @@ async fn handle_socket(
         _ = websocket_task_handle => {
             tracing::info!("Websocket handler finished");
         }
-        _ = client_receive_handle => {
+        _ = &mut client_receive_handle => {
             // client receiver finished, which means the websocket is closed
             // session mannager failed to send the control message, which means the other end is closed
-            let _ = session_manager.send_control_command(user_id, crate::core::session_manager::WebsocketControlMessage::Close).await;
+            let close = CloseMessage::new(close_code::NORMAL, "");
+            let _ = websocket.send_command(WebsocketControlMessage::Close(close)).await;
         }
+        _ = mailbox.closed() => {
+            tracing::warn!(%user_id, metrics = ?mailbox.metrics(), "client fell behind, disconnecting");
+            let close = CloseMessage::new(close_code::AGAIN, "Too far behind, reconnect to catch up");
+            let _ = websocket.send_command(WebsocketControlMessage::Close(close)).await;
+        }
+    }
+
+    // the session itself is removed by its websocket task once that ends
+    broker::unsubscribe_user(&broker, user_id, &mailbox);
+
+    // the websocket is gone, let a message that is being stored finish before shutdown
+    if !client_receive_handle.is_finished() {
+        let _ = client_receive_handle.await;
     }
 }
+
+/// Tells the client a message of theirs was not taken.
+async fn send_rejected(websocket: &SafeWebsocket, rejected: api_models::chat::RejectedBody) {
+    let rejected = api_models::chat::ServerMessage::Rejected(rejected);
+    let Ok(text) = serde_json::to_string(&rejected) else {
+        return;
+    };
+    let _ = websocket
+        .send_command(WebsocketControlMessage::SendMessage(
+            axum::extract::ws::Message::Text(text),
+        ))
+        .await;
+}
@@ -0,0 +1,91 @@
+use api_models::chat::{ConversationSummary, HistoryPage, HistoryQuery, UnreadCount};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use uuid::Uuid;
+
+use crate::{
+    context::RuimContext, db::Database, handler::ApiError, service::auth::UserTokenExtractor,
+};
+
+const DEFAULT_HISTORY_LIMIT: i64 = 50;
+/// Upper bound for the messages returned in one page.
+const MAX_HISTORY_LIMIT: i64 = 100;
+
+pub(crate) fn router() -> Router<RuimContext> {
+    Router::new()
+        .route("/", get(list_conversations))
+        .route("/unread", get(unread_counts))
+        .route("/:peer_id/messages", get(history))
+}
+
+/// Every conversation of the user, the most recently active first.
+async fn list_conversations(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+) -> Result<Json<Vec<ConversationSummary>>, ApiError> {
+    let conversations = db
+        .get_conversations(user_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to list conversations"))
+        .map_err(|_| ApiError::msg("Failed to list conversations"))?;
+
+    Ok(Json(conversations.into_iter().map(Into::into).collect()))
+}
+
+/// Unread messages per conversation, conversations without any are left out.
+async fn unread_counts(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+) -> Result<Json<Vec<UnreadCount>>, ApiError> {
+    let counts = db
+        .unread_counts(user_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to count unread messages"))
+        .map_err(|_| ApiError::msg("Failed to count unread messages"))?;
+
+    Ok(Json(counts))
+}
+
+/// One page of the messages between the user and `peer_id`.
+/// Nobody but the two of them can read it, the conversation is looked up by the caller's id.
+async fn history(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+    Path(peer_id): Path<Uuid>,
+    Query(HistoryQuery { before, limit }): Query<HistoryQuery>,
+) -> Result<Json<HistoryPage>, ApiError> {
+    let limit = history_limit(limit)?;
+
+    let messages = db
+        .get_conversation_messages(user_id, peer_id, before, limit)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to get chat history"))
+        .map_err(|_| ApiError::msg("Failed to get chat history"))?;
+
+    let next_before = next_before(&messages, limit, |message| message.message_id);
+    Ok(Json(HistoryPage {
+        messages: messages.into_iter().map(Into::into).collect(),
+        next_before,
+    }))
+}
+
+/// The requested page size, shared by every history endpoint.
+pub(crate) fn history_limit(limit: Option<i64>) -> Result<i64, ApiError> {
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    if !(1..=MAX_HISTORY_LIMIT).contains(&limit) {
+        return Err(
+            ApiError::msg(&format!("limit must be between 1 and {MAX_HISTORY_LIMIT}"))
+                .code(StatusCode::BAD_REQUEST),
+        );
+    }
+    Ok(limit)
+}
+
+/// The cursor of the next page, `None` if this full page was the last one.
+pub(crate) fn next_before<T>(page: &[T], limit: i64, id: impl Fn(&T) -> i32) -> Option<i32> {
+    page.last().filter(|_| page.len() as i64 == limit).map(id)
+}
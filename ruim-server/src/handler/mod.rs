@@ -8,6 +8,10 @@ use axum::{
 use serde_json::json;
 
 pub mod chat;
+pub mod conversation;
+pub mod presence;
+pub mod room;
+pub mod session;
 pub mod user;
 
 #[derive(Debug)]
@@ -0,0 +1,57 @@
+use api_models::chat::{PresenceBody, PresenceQuery};
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+
+use crate::{
+    context::RuimContext, core::presence::Presence, db::Database, handler::ApiError,
+    service::auth::UserTokenExtractor,
+};
+
+/// Upper bound for the users asked about in one request.
+const MAX_PRESENCE_QUERY: usize = 100;
+
+pub(crate) fn router() -> Router<RuimContext> {
+    Router::new().route("/", post(query_presence))
+}
+
+/// Presence of each requested user that is the caller or one of their friends,
+/// in no particular order. Other ids are left out.
+async fn query_presence(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+    State(presence): State<Presence>,
+    Json(PresenceQuery { user_ids }): Json<PresenceQuery>,
+) -> Result<Json<Vec<PresenceBody>>, ApiError> {
+    if user_ids.len() > MAX_PRESENCE_QUERY {
+        return Err(
+            ApiError::msg(&format!("At most {MAX_PRESENCE_QUERY} users per request"))
+                .code(StatusCode::BAD_REQUEST),
+        );
+    }
+
+    let friend_ids = db
+        .query_friend_ids(user_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to get friends"))
+        .map_err(|_| ApiError::msg("Failed to get presence"))?;
+    let user_ids: Vec<_> = user_ids
+        .into_iter()
+        .filter(|id| *id == user_id || friend_ids.contains(id))
+        .collect();
+
+    let last_seen = db
+        .get_last_seen(&user_ids)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to get last seen"))
+        .map_err(|_| ApiError::msg("Failed to get presence"))?;
+
+    Ok(Json(
+        last_seen
+            .into_iter()
+            .map(|(user_id, last_seen)| PresenceBody {
+                user_id,
+                status: presence.status(user_id),
+                last_seen: last_seen.map(|t| t.to_string()),
+            })
+            .collect(),
+    ))
+}
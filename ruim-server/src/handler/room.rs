@@ -0,0 +1,253 @@
+use api_models::{
+    chat::HistoryQuery,
+    room::{ApiRoom, CreateRoomBody, InviteBody, RoomHistoryPage, RoomMember},
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use uuid::Uuid;
+
+use crate::{
+    context::RuimContext,
+    db::{DBError, Database},
+    handler::ApiError,
+    model::room::RoomRole,
+    service::auth::UserTokenExtractor,
+};
+
+use super::{
+    conversation::{history_limit, next_before},
+    GenericResponse,
+};
+
+pub(crate) fn router() -> Router<RuimContext> {
+    Router::new()
+        .route("/", post(create_room).get(list_rooms))
+        .route("/:room_id/members", get(list_members).post(invite))
+        .route("/:room_id/members/:user_id", delete(kick))
+        .route("/:room_id/leave", post(leave))
+        .route("/:room_id/messages", get(history))
+}
+
+/// The caller creates the room and owns it.
+async fn create_room(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+    Json(CreateRoomBody { name }): Json<CreateRoomBody>,
+) -> Result<Json<ApiRoom>, ApiError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ApiError::msg("Room name must not be empty").code(StatusCode::BAD_REQUEST));
+    }
+
+    let room = db
+        .create_room(user_id, name)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to create room"))
+        .map_err(|_| ApiError::msg("Failed to create room"))?;
+
+    Ok(Json(room.into()))
+}
+
+/// Every room the caller is a member of.
+async fn list_rooms(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+) -> Result<Json<Vec<ApiRoom>>, ApiError> {
+    let rooms = db
+        .get_rooms(user_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to get rooms"))
+        .map_err(|_| ApiError::msg("Failed to get rooms"))?;
+
+    Ok(Json(rooms.into_iter().map(Into::into).collect()))
+}
+
+async fn list_members(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+    Path(room_id): Path<i32>,
+) -> Result<Json<Vec<RoomMember>>, ApiError> {
+    role_in(&db, room_id, user_id).await?;
+
+    let members = db
+        .get_room_members(room_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to get room members"))
+        .map_err(|_| ApiError::msg("Failed to get room members"))?;
+
+    members
+        .into_iter()
+        .map(|member| {
+            let role = RoomRole::try_from(member.role)
+                .inspect_err(|err| tracing::error!(?err, "Invalid room role"))
+                .map_err(|_| ApiError::msg("Failed to get room members"))?;
+            Ok(RoomMember {
+                user_id: member.user_id,
+                role: role.into(),
+                joined_at: member.joined_at.to_string(),
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Json)
+}
+
+/// Adds a user to the room. Owners and admins may invite, with a role below their own,
+/// and only users who would take a direct message from them.
+async fn invite(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+    Path(room_id): Path<i32>,
+    Json(InviteBody {
+        user_id: invitee_id,
+        role,
+    }): Json<InviteBody>,
+) -> Result<GenericResponse, ApiError> {
+    let role = role.map(RoomRole::from).unwrap_or(RoomRole::Member);
+    if !role_in(&db, room_id, user_id).await?.can_manage(role) {
+        return Err(
+            ApiError::msg("Not allowed to invite with this role").code(StatusCode::FORBIDDEN)
+        );
+    }
+
+    let accepts = db
+        .accepts_messages_from(invitee_id, user_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to check the invitee"))
+        .map_err(|_| ApiError::msg("Failed to add room member"))?;
+    invitee_consents(accepts)?;
+
+    let added = db
+        .add_room_member(room_id, invitee_id, role)
+        .await
+        .map_err(|err| {
+            if is_foreign_key_violation(&err) {
+                return ApiError::msg("User not found").code(StatusCode::NOT_FOUND);
+            }
+            tracing::error!(?err, "Failed to add room member");
+            ApiError::msg("Failed to add room member")
+        })?;
+
+    if !added {
+        return Err(ApiError::msg("User is already a member").code(StatusCode::CONFLICT));
+    }
+    Ok(GenericResponse::default().msg("User added to room"))
+}
+
+/// Removes a member. Owners and admins may kick members below their own role.
+async fn kick(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+    Path((room_id, member_id)): Path<(i32, Uuid)>,
+) -> Result<GenericResponse, ApiError> {
+    let role = role_in(&db, room_id, user_id).await?;
+    let member_role = db
+        .get_room_role(room_id, member_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to get room role"))
+        .map_err(|_| ApiError::msg("Failed to kick room member"))?
+        .ok_or_else(|| ApiError::msg("Member not found").code(StatusCode::NOT_FOUND))?;
+
+    if !role.can_manage(member_role) {
+        return Err(ApiError::msg("Not allowed to kick this member").code(StatusCode::FORBIDDEN));
+    }
+
+    db.remove_room_member(room_id, member_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to remove room member"))
+        .map_err(|_| ApiError::msg("Failed to kick room member"))?;
+
+    Ok(GenericResponse::default().msg("Member removed from room"))
+}
+
+/// Anyone but the owner may leave.
+async fn leave(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+    Path(room_id): Path<i32>,
+) -> Result<GenericResponse, ApiError> {
+    if role_in(&db, room_id, user_id).await? == RoomRole::Owner {
+        return Err(ApiError::msg("The owner cannot leave the room").code(StatusCode::BAD_REQUEST));
+    }
+
+    db.remove_room_member(room_id, user_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to remove room member"))
+        .map_err(|_| ApiError::msg("Failed to leave room"))?;
+
+    Ok(GenericResponse::default().msg("Left room"))
+}
+
+/// One page of the room's messages, for members only.
+async fn history(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(db): State<Database>,
+    Path(room_id): Path<i32>,
+    Query(HistoryQuery { before, limit }): Query<HistoryQuery>,
+) -> Result<Json<RoomHistoryPage>, ApiError> {
+    let limit = history_limit(limit)?;
+    role_in(&db, room_id, user_id).await?;
+
+    let messages = db
+        .get_room_messages(room_id, before, limit)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to get room history"))
+        .map_err(|_| ApiError::msg("Failed to get room history"))?;
+
+    let next_before = next_before(&messages, limit, |message| message.message_id);
+    Ok(Json(RoomHistoryPage {
+        messages: messages.into_iter().map(Into::into).collect(),
+        next_before,
+    }))
+}
+
+/// The caller's role; rooms they are not in look the same as rooms that do not exist.
+async fn role_in(db: &Database, room_id: i32, user_id: Uuid) -> Result<RoomRole, ApiError> {
+    db.get_room_role(room_id, user_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to get room role"))
+        .map_err(|_| ApiError::msg("Failed to get room"))?
+        .ok_or_else(|| ApiError::msg("Room not found").code(StatusCode::NOT_FOUND))
+}
+
+/// Members get every room message pushed to them, so inviting takes the same consent
+/// as a direct message, see [`Database::accepts_messages_from`].
+fn invitee_consents(accepts: Option<bool>) -> Result<(), ApiError> {
+    match accepts {
+        Some(true) => Ok(()),
+        Some(false) => {
+            Err(ApiError::msg("User only accepts invites from friends").code(StatusCode::FORBIDDEN))
+        }
+        None => Err(ApiError::msg("User not found").code(StatusCode::NOT_FOUND)),
+    }
+}
+
+fn is_foreign_key_violation(err: &DBError) -> bool {
+    err.get_sqlx_error()
+        .and_then(|err| err.as_database_error())
+        .is_some_and(|err| err.is_foreign_key_violation())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+
+    use super::*;
+
+    #[test]
+    fn test_only_consenting_users_can_be_invited() {
+        assert!(invitee_consents(Some(true)).is_ok());
+
+        let status = |accepts| {
+            invitee_consents(accepts)
+                .unwrap_err()
+                .into_response()
+                .status()
+        };
+        assert_eq!(status(Some(false)), StatusCode::FORBIDDEN);
+        assert_eq!(status(None), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,46 @@
+use api_models::chat::ChatSession;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get},
+    Json, Router,
+};
+use uuid::Uuid;
+
+use crate::{
+    context::RuimContext, core::session_manager::SessionManager, handler::ApiError,
+    service::auth::UserTokenExtractor,
+};
+
+use super::GenericResponse;
+
+pub(crate) fn router() -> Router<RuimContext> {
+    Router::new()
+        .route("/", get(list_sessions))
+        .route("/:connection_id", delete(kick_session))
+}
+
+/// Every device the user is connected with.
+async fn list_sessions(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(session_manager): State<SessionManager>,
+) -> Json<Vec<ChatSession>> {
+    Json(session_manager.sessions(user_id))
+}
+
+/// Disconnects one of the user's own devices.
+async fn kick_session(
+    UserTokenExtractor { user_id }: UserTokenExtractor,
+    State(session_manager): State<SessionManager>,
+    Path(connection_id): Path<Uuid>,
+) -> Result<GenericResponse, ApiError> {
+    let kicked = session_manager
+        .kick_session(user_id, connection_id)
+        .await
+        .inspect_err(|err| tracing::error!(?err, "Failed to kick session"))?;
+
+    if !kicked {
+        return Err(ApiError::msg("Session not found").code(StatusCode::NOT_FOUND));
+    }
+    Ok(GenericResponse::default().msg("Session closed"))
+}
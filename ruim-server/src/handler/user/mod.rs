@@ -1,10 +1,10 @@
-use api_models::user::{ApiUser, LoginBody, RegisterBody};
+use api_models::user::{ApiUser, LoginBody, PublicUser, PublicUsersQuery, RegisterBody};
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 use axum::{
-    extract::State,
+    extract::{Query, State},
     http::StatusCode,
     response::IntoResponse,
     routing::{get, put},
@@ -26,6 +26,7 @@ pub fn router(_state: RuimContext) -> Router<RuimContext> {
         .route("/signup", put(register))
         .route("/login", get(login))
         .route("/detail", get(get_user))
+        .route("/public", get(get_public_users))
         .nest("/friend", friendship::router())
 }
 
@@ -110,18 +111,34 @@ pub async fn get_user(
     Ok(Json(api_user))
 }
 
+/// Upper bound for the users returned in one page of the public directory.
+const MAX_PUBLIC_USERS_LIMIT: i64 = 100;
+
+/// The public directory: users who chose to be shown in the public chat.
 pub async fn get_public_users(
+    UserTokenExtractor { user_id: _ }: UserTokenExtractor,
     State(db): State<Database>,
-    page: i64,
-    limit: i64,
-) -> Result<impl IntoResponse, ApiError> {
+    Query(PublicUsersQuery { page, limit }): Query<PublicUsersQuery>,
+) -> Result<Json<Vec<PublicUser>>, ApiError> {
+    let page = page.unwrap_or(1);
+    let limit = limit.unwrap_or(20);
+    if !(1..=MAX_PUBLIC_USERS_LIMIT).contains(&limit) {
+        return Err(ApiError::msg(&format!(
+            "limit must be between 1 and {MAX_PUBLIC_USERS_LIMIT}"
+        ))
+        .code(StatusCode::BAD_REQUEST));
+    }
+    // the offset is computed from both, keep it representable
+    let Some(offset) = (page >= 1).then(|| (page - 1).checked_mul(limit)).flatten() else {
+        return Err(ApiError::msg("page is out of range").code(StatusCode::BAD_REQUEST));
+    };
+
     let users = db
-        .get_public_users(page, limit)
+        .get_public_users(offset, limit)
         .await
         .map_err(|_| ApiError::msg("Failed to get users"))?;
 
-    let api_users: Vec<ApiUser> = users.into_iter().map(|u| u.into()).collect();
-    Ok(Json(api_users))
+    Ok(Json(users.into_iter().map(|u| u.into()).collect()))
 }
 
 #[cfg(test)]
@@ -7,4 +7,48 @@ pub struct Message {
     pub receiver_id: Uuid,
     pub content: String,
     pub created_at: sqlx::types::time::OffsetDateTime,
+    pub delivered_at: Option<sqlx::types::time::OffsetDateTime>,
+    pub read_at: Option<sqlx::types::time::OffsetDateTime>,
+}
+
+impl From<Message> for api_models::chat::HistoryMessage {
+    fn from(message: Message) -> Self {
+        Self {
+            message_id: message.message_id,
+            sender_id: message.sender_id,
+            receiver_id: message.receiver_id,
+            message: message.content,
+            created_at: message.created_at.to_string(),
+            delivered_at: message.delivered_at.map(|t| t.to_string()),
+            read_at: message.read_at.map(|t| t.to_string()),
+        }
+    }
+}
+
+/// The newest message with one peer, see [`crate::db::Database::get_conversations`].
+#[derive(Debug)]
+pub struct Conversation {
+    pub peer_id: Uuid,
+    pub peer_username: String,
+    pub last_message_id: i32,
+    pub last_sender_id: Uuid,
+    pub last_message_preview: String,
+    pub last_message_at: sqlx::types::time::OffsetDateTime,
+    pub unread: i64,
+}
+
+impl From<Conversation> for api_models::chat::ConversationSummary {
+    fn from(conversation: Conversation) -> Self {
+        Self {
+            peer: api_models::user::PeerUser {
+                user_id: conversation.peer_id,
+                username: conversation.peer_username,
+            },
+            last_message_id: conversation.last_message_id,
+            last_sender_id: conversation.last_sender_id,
+            last_message_preview: conversation.last_message_preview,
+            last_message_at: conversation.last_message_at.to_string(),
+            unread: conversation.unread,
+        }
+    }
 }
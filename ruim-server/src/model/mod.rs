@@ -1,2 +1,3 @@
 pub mod message;
+pub mod room;
 pub mod user;
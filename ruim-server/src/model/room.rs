@@ -0,0 +1,104 @@
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use uuid::Uuid;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Room {
+    pub room_id: i32,
+    pub name: String,
+    pub owner_id: Uuid,
+    pub created_at: sqlx::types::time::OffsetDateTime,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RoomMember {
+    pub room_id: i32,
+    pub user_id: Uuid,
+    pub role: i16,
+    pub joined_at: sqlx::types::time::OffsetDateTime,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RoomMessage {
+    pub message_id: i32,
+    pub room_id: i32,
+    pub sender_id: Uuid,
+    pub content: String,
+    pub created_at: sqlx::types::time::OffsetDateTime,
+}
+
+/// Stored in `room_members.role`, lower is more privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive, IntoPrimitive)]
+#[repr(i16)]
+pub enum RoomRole {
+    Owner = 1,
+    Admin = 2,
+    Member = 3,
+}
+
+impl RoomRole {
+    /// Whether this role may add, remove or appoint someone with the `other` role.
+    /// Only owners and admins manage others, and only those below them.
+    pub fn can_manage(self, other: RoomRole) -> bool {
+        self != RoomRole::Member && self < other
+    }
+}
+
+impl From<RoomRole> for api_models::room::RoomRole {
+    fn from(role: RoomRole) -> Self {
+        match role {
+            RoomRole::Owner => Self::Owner,
+            RoomRole::Admin => Self::Admin,
+            RoomRole::Member => Self::Member,
+        }
+    }
+}
+
+impl From<api_models::room::RoomRole> for RoomRole {
+    fn from(role: api_models::room::RoomRole) -> Self {
+        match role {
+            api_models::room::RoomRole::Owner => Self::Owner,
+            api_models::room::RoomRole::Admin => Self::Admin,
+            api_models::room::RoomRole::Member => Self::Member,
+        }
+    }
+}
+
+impl From<Room> for api_models::room::ApiRoom {
+    fn from(room: Room) -> Self {
+        Self {
+            room_id: room.room_id,
+            name: room.name,
+            owner_id: room.owner_id,
+            created_at: room.created_at.to_string(),
+        }
+    }
+}
+
+impl From<RoomMessage> for api_models::room::RoomMessageBody {
+    fn from(message: RoomMessage) -> Self {
+        Self {
+            room_id: message.room_id,
+            message_id: message.message_id,
+            sender_id: message.sender_id,
+            message: message.content,
+            created_at: message.created_at.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_manage() {
+        assert!(RoomRole::Owner.can_manage(RoomRole::Admin));
+        assert!(RoomRole::Owner.can_manage(RoomRole::Member));
+        assert!(RoomRole::Admin.can_manage(RoomRole::Member));
+
+        assert!(!RoomRole::Owner.can_manage(RoomRole::Owner));
+        assert!(!RoomRole::Admin.can_manage(RoomRole::Admin));
+        assert!(!RoomRole::Admin.can_manage(RoomRole::Owner));
+        assert!(!RoomRole::Member.can_manage(RoomRole::Member));
+    }
+}
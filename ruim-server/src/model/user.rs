@@ -1,4 +1,4 @@
-use api_models::user::ApiUser;
+use api_models::user::{ApiUser, PublicUser};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 use uuid::Uuid;
@@ -61,3 +61,13 @@ impl From<User> for ApiUser {
         }
     }
 }
+
+impl From<User> for PublicUser {
+    fn from(val: User) -> Self {
+        PublicUser {
+            user_id: val.user_id,
+            username: val.username,
+            accept_public_chat: val.accept_public_chat,
+        }
+    }
+}